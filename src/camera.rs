@@ -3,6 +3,7 @@ use winit::event::*;
 use winit::dpi::PhysicalPosition;
 use std::time::Duration;
 use std::f32::consts::FRAC_PI_2;
+use crate::cli;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -14,11 +15,21 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
+// Mouse sensitivities, per pixel of motion and per line of scroll
+const ORBIT_PER_PIXEL: f32 = 0.01; // radians
+const PAN_PER_PIXEL: f32 = 0.004;  // world units
+const ZOOM_PER_LINE: f32 = 1.1;
+const PIXELS_PER_LINE: f32 = 100.0;
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    model: [[f32; 4]; 4],  // mesh to world, for lighting
+    normal: [[f32; 4]; 4], // inverse transpose of model
+    viewport: [f32; 4],    // width and height in pixels, for point sizes
 }
 
 impl CameraUniform {
@@ -26,9 +37,15 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            model: cgmath::Matrix4::identity().into(),
+            normal: cgmath::Matrix4::identity().into(),
+            viewport: [1.0; 4],
         }
     }
 
+    // Mesh to clip space
+    pub fn view_proj(&self) -> Matrix4<f32> { self.view_proj.into() }
+
     pub fn update_view_proj(
         &mut self,
         camera: &Camera,
@@ -36,9 +53,15 @@ impl CameraUniform {
         model_view: &ModelView
     ) {
         // self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix()).into();
+        let model = model_view.calc_matrix();
         self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()
-            * model_view.calc_matrix()).into();
+            * model).into();
+        self.model = model.into();
+        self.normal = model.invert().unwrap_or(Matrix4::identity())
+            .transpose().into();
+        let (width, height) = projection.viewport;
+        self.viewport = [width as f32, height as f32, 0.0, 0.0];
     }
 }
 
@@ -83,6 +106,8 @@ impl Camera {
 }
 
 pub struct Projection {
+    pub mode: cli::Projection,
+    viewport: (u32, u32),
     aspect: f32,
     fovy: Rad<f32>,
     znear: f32,
@@ -91,6 +116,7 @@ pub struct Projection {
 
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(
+        mode: cli::Projection,
         width: u32,
         height: u32,
         fovy: F,
@@ -98,6 +124,8 @@ impl Projection {
         zfar: f32,
     ) -> Self {
         Self {
+            mode,
+            viewport: (width, height),
             aspect: width as f32 / height as f32,
             fovy: fovy.into(),
             znear,
@@ -106,45 +134,62 @@ impl Projection {
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
+        self.viewport = (width, height);
         self.aspect = width as f32 / height as f32;
     }
 
+    // Both keep the aspect ratio of the window. The orthographic view
+    // fits a square a little larger than the surface into the window.
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-            // OPENGL_TO_WGPU_MATRIX * perspective(
-            //     self.fovy, self.aspect, self.znear, self.zfar)    
-            let fovy = cgmath::PerspectiveFov {
-                aspect: self.aspect,
-                fovy: self.fovy,
-                near: self.znear,
-                far: self.zfar,
-            };
-            let p = fovy.to_perspective();
-            // println!("{:?}", p);
-
-            // OPENGL_TO_WGPU_MATRIX * cgmath::ortho(
-            //     p.left, p.right, p.bottom, p.top, p.near, p.far)
-            OPENGL_TO_WGPU_MATRIX * cgmath::ortho(
-                -1.1, 1.1, -1.1, 1.1, p.near, p.far)
+        match self.mode {
+            cli::Projection::Perspective => OPENGL_TO_WGPU_MATRIX
+                * perspective(self.fovy, self.aspect, self.znear, self.zfar),
+            cli::Projection::Ortho => {
+                let half = 1.1;
+                let (x, y) = if self.aspect >= 1.0 {
+                    (half * self.aspect, half)
+                } else {
+                    (half, half / self.aspect)
+                };
+                OPENGL_TO_WGPU_MATRIX * cgmath::ortho(
+                    -x, x, -y, y, self.znear, self.zfar)
+            }
         }
+    }
 }
 
 pub struct ModelView {
     x_theta: Rad<f32>,
     y_theta: Rad<f32>,
+    pan: Vector2<f32>, // screen aligned shift
+    zoom: f32,         // scale about the pan centre
+    home: (Rad<f32>, Rad<f32>),
     // mat: Matrix4<f32>,
 }
 
 impl ModelView {
     pub fn new<F: Into<Rad<f32>>>(x_theta: F, y_theta: F) -> Self {
+        let home = (x_theta.into(), y_theta.into());
         Self {
-            x_theta: x_theta.into(),
-            y_theta: y_theta.into(),
+            x_theta: home.0,
+            y_theta: home.1,
+            pan: Vector2::zero(),
+            zoom: 1.0,
+            home,
         }
     }
 
+    // Back to the view it was created with
+    pub fn reset(&mut self) {
+        *self = Self::new(self.home.0, self.home.1);
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * Matrix4::from_angle_x(self.x_theta) *
-            Matrix4::from_angle_y(self.y_theta)
+        OPENGL_TO_WGPU_MATRIX
+            * Matrix4::from_translation(self.pan.extend(0.0))
+            * Matrix4::from_scale(self.zoom)
+            * Matrix4::from_angle_x(self.x_theta)
+            * Matrix4::from_angle_y(self.y_theta)
     }
 }
 
@@ -161,6 +206,13 @@ pub struct CameraController {
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    // Mouse drags, left orbits and right pans
+    orbiting: bool,
+    panning: bool,
+    orbit: Vector2<f32>, // pixels moved since the last update
+    pan: Vector2<f32>,
+    last_click: Option<instant::Instant>,
+    reset: bool,
 }
 
 impl CameraController {
@@ -177,6 +229,12 @@ impl CameraController {
             scroll: 0.0,
             speed,
             sensitivity,
+            orbiting: false,
+            panning: false,
+            orbit: Vector2::zero(),
+            pan: Vector2::zero(),
+            last_click: None,
+            reset: false,
         }
     }
 
@@ -227,15 +285,50 @@ impl CameraController {
         }
     }
 
+    // Starts and stops drags, two left presses in quick succession reset
+    // the view.
+    pub fn process_button(
+        &mut self, button: MouseButton, state: ElementState
+    ) -> bool {
+        let pressed = state == ElementState::Pressed;
+        match button {
+            MouseButton::Left => {
+                self.orbiting = pressed;
+                if pressed {
+                    let now = instant::Instant::now();
+                    self.reset = self.last_click
+                        .is_some_and(|last| now - last < DOUBLE_CLICK);
+                    self.last_click = if self.reset { None } else { Some(now) };
+                }
+                true
+            }
+            MouseButton::Right => {
+                self.panning = pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Buttons released outside the window are never seen
+    pub fn release_buttons(&mut self) {
+        self.orbiting = false;
+        self.panning = false;
+    }
+
+    pub fn is_dragging(&self) -> bool { self.orbiting || self.panning }
+
+    // Raw motion of the mouse in pixels, from DeviceEvent::MouseMotion
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        let delta = Vector2::new(mouse_dx as f32, mouse_dy as f32);
+        if self.orbiting { self.orbit += delta; }
+        else if self.panning { self.pan += delta; }
     }
 
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = -match delta {
+        self.scroll += match delta {
             // I'm assuming a line is about 100 pixels
-            MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
+            MouseScrollDelta::LineDelta(_, scroll) => scroll * PIXELS_PER_LINE,
             MouseScrollDelta::PixelDelta(PhysicalPosition {
                 y: scroll,
                 ..
@@ -252,6 +345,21 @@ impl CameraController {
             += Rad(-self.rotate_vertical) * self.sensitivity * dt;
         model_view.y_theta
             += Rad(self.rotate_horizontal) * self.sensitivity * dt;
+
+        // Mouse input is consumed once, however long the frame took
+        if self.reset {
+            model_view.reset();
+            self.reset = false;
+        }
+        model_view.x_theta += Rad(self.orbit.y * ORBIT_PER_PIXEL);
+        model_view.y_theta += Rad(self.orbit.x * ORBIT_PER_PIXEL);
+        // Screen y is down, world y is up
+        model_view.pan += Vector2::new(self.pan.x, -self.pan.y)
+            * PAN_PER_PIXEL;
+        model_view.zoom *= ZOOM_PER_LINE.powf(self.scroll / PIXELS_PER_LINE);
+        self.orbit = Vector2::zero();
+        self.pan = Vector2::zero();
+        self.scroll = 0.0;
     }
 
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
@@ -264,14 +372,8 @@ impl CameraController {
         camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
         camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
-        // Move in/out (aka. "zoom")
-        // Note: this isn't an actual zoom. The camera's position
-        // changes when zooming. I've added this to make it easier
-        // to get closer to an object you want to focus on.
-        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
-        let scrollward = Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-        self.scroll = 0.0;
+        // Scrolling zooms the model in update_model_view, moving the
+        // camera does nothing under an orthographic projection.
 
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
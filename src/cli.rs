@@ -1,6 +1,9 @@
+#![allow(clippy::needless_arbitrary_self_type)]
+
 use clap::Parser;
+use clap::Subcommand;
 use clap::ValueEnum;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 // use image::io::Reader as ImageReader;
 
 // I had two structs, one Cli that interprested the command line and the
@@ -15,8 +18,23 @@ use std::path::PathBuf;
 const RES_DEFAULT: u32 = 51;
 const XRES_DEFAULT: u32 = 51;
 const YRES_DEFAULT: u32 = 51;
+const PIXEL_STEP_DEFAULT: u32 = 1;
+const MAX_VERTICES_DEFAULT: u32 = 1 << 20;
+const PIXEL_ASPECT_DEFAULT: f32 = 1.0;
 const Z_OFFSET_DEFAULT: f32 = 0.25;
 const Z_SCALE_DEFAULT: f32 = 1.0;
+const LIGHT_AZIMUTH_DEFAULT: f32 = 135.0;
+const LIGHT_ELEVATION_DEFAULT: f32 = 45.0;
+const AMBIENT_DEFAULT: f32 = 0.3;
+const SPECULAR_DEFAULT: f32 = 0.3;
+const SHININESS_DEFAULT: f32 = 32.0;
+const CONTOUR_WIDTH_DEFAULT: f32 = 1.0;
+const WIRE_OPACITY_DEFAULT: f32 = 0.5;
+const POINT_SIZE_DEFAULT: f32 = 3.0;
+const POWER_DEFAULT: f32 = 0.5;
+pub const MAX_CONTOUR_LEVELS: usize = 16;
+const WIDTH_DEFAULT: u32 = 800;
+const HEIGHT_DEFAULT: u32 = 600;
 
 #[derive(Parser,Default,Debug)]
 #[clap(author="Author Name", version, about)]
@@ -26,25 +44,54 @@ pub struct Cli {
     image_name: PathBuf,
 
     #[arg(short, long)]
-    /// Wire frame display
+    /// Wire frame display, the same as --display wire
     wire: bool,
 
+    #[arg(value_enum, long, default_value_t=DisplayMode::Fill)]
+    /// How the surface is drawn, G cycles it in the viewer
+    display: DisplayMode,
+
     #[arg(value_enum, short, long, default_value_t=Channel::All)]
     /// Channel to be displayed
     channel: Channel,
 
-    #[arg(short, long, default_value_t=XRES_DEFAULT)]
-    /// Resolution of the display grid in both x and y
-    resolution: u32,
+    #[arg(long, value_delimiter = ',')]
+    /// Comma separated weights of red, green, blue and alpha summed by the
+    /// mix channel [default: a third each of red, green and blue]
+    mix: Vec<f32>,
+
+    #[arg(short, long, value_parser=parse_resolution, default_value_t=Resolution::Count(RES_DEFAULT))]
+    /// Resolution of the display grid along the longer side of the image,
+    /// the other side is in proportion. 'full' puts a vertex on every
+    /// pixel-step pixels, 'auto' is full up to max-vertices
+    resolution: Resolution,
+
+    #[arg(long, default_value_t=PIXEL_STEP_DEFAULT)]
+    /// Pixels between grid vertexes at full resolution
+    pixel_step: u32,
+
+    #[arg(long, default_value_t=MAX_VERTICES_DEFAULT)]
+    /// Most vertexes in the grid at auto resolution
+    max_vertices: u32,
 
-    #[arg(short, long, default_value_t=XRES_DEFAULT)]
+    #[arg(short, long, value_parser=parse_count, default_value_t=XRES_DEFAULT)]
     /// X resolution of the display grid
     xres: u32,
 
-    #[arg(short, long, default_value_t=YRES_DEFAULT)]
+    #[arg(short, long, value_parser=parse_count, default_value_t=YRES_DEFAULT)]
     /// Y resolution of the display grid
     yres: u32,
 
+    #[arg(long, default_value_t=PIXEL_ASPECT_DEFAULT)]
+    /// Width of a pixel relative to its height, for instruments with
+    /// anisotropic pixels
+    pixel_aspect: f32,
+
+    #[arg(long)]
+    /// Draw the image as tiles with a level of detail, always on for images
+    /// larger than the GPU can hold in one texture
+    lod: bool,
+
     #[arg(short, long, default_value_t=Z_OFFSET_DEFAULT)]
     /// Z displacement between rgb color grids
     offset: f32,
@@ -53,59 +100,356 @@ pub struct Cli {
     /// Z scale factor
     scale: f32,
 
+    #[arg(value_enum, long)]
+    /// How image values map to heights [default: srgb for 8 bit images,
+    /// linear for deeper ones]
+    height_encoding: Option<Encoding>,
+
+    #[arg(value_enum, long, default_value_t=Sampling::Bilinear)]
+    /// How heights between pixels are found when the grid doesn't fall on
+    /// the pixels
+    sampling: Sampling,
+
+    #[arg(long)]
+    /// Sample every grid point from the full image even when the grid is
+    /// coarser, rather than from a box filtered mip level
+    no_mipmaps: bool,
+
+    #[arg(long, value_delimiter = ',', value_parser=parse_filter)]
+    /// Filter the heights, not the colours, with these comma separated
+    /// filters in order: blur:SIGMA, median:RADIUS, unsharp:SIGMA[:AMOUNT],
+    /// open:RADIUS, close:RADIUS and sobel, sizes in pixels
+    filter: Vec<Filter>,
+
+    #[arg(value_enum, long, default_value_t=Curve::Linear)]
+    /// How channel values map to heights, after --range or --normalize.
+    /// Colours and contours follow the heights
+    transfer: Curve,
+
+    #[arg(long, value_parser=parse_power, default_value_t=POWER_DEFAULT)]
+    /// Exponent of the power transfer, 0.5 for a square root
+    power: f32,
+
+    #[arg(long, value_parser=parse_range)]
+    /// Channel values mapped onto the full height as MIN,MAX, values
+    /// outside are clamped
+    range: Option<[f32; 2]>,
+
+    #[arg(long)]
+    /// Map the range of the channel values in the image onto the full
+    /// height
+    normalize: bool,
+
+    #[arg(long)]
+    /// Turn the heights upside down, high values lowest
+    invert: bool,
+
+    #[arg(value_enum, long)]
+    /// Colour the surface by height with this map instead of the image
+    colormap: Option<Colormap>,
+
+    #[arg(long)]
+    /// Draw contour lines this far apart in image value, 0 to 1 for a
+    /// single channel
+    contour_interval: Option<f32>,
+
+    #[arg(long, value_delimiter = ',')]
+    /// Draw contour lines at these comma separated image values
+    contour_levels: Vec<f32>,
+
+    #[arg(long, default_value_t=CONTOUR_WIDTH_DEFAULT)]
+    /// Width of contour lines in pixels
+    contour_width: f32,
+
+    #[arg(long, value_parser=parse_color, default_value="000000")]
+    /// Colour of contour lines as hex rrggbb
+    contour_color: [f32; 3],
+
+    #[arg(long, value_parser=parse_color, default_value="ffffff")]
+    /// Colour of the grid lines drawn over the surface as hex rrggbb
+    wire_color: [f32; 3],
+
+    #[arg(long, default_value_t=WIRE_OPACITY_DEFAULT)]
+    /// Opacity of the grid lines drawn over the surface, 0 to 1
+    wire_opacity: f32,
+
+    #[arg(long, default_value_t=POINT_SIZE_DEFAULT)]
+    /// Width in pixels of the points of --display points
+    point_size: f32,
+
+    #[arg(value_enum, long, default_value_t=Projection::Ortho)]
+    /// Camera projection, P toggles it in the viewer
+    projection: Projection,
+
+    #[arg(value_enum, long, default_value_t=Lighting::None)]
+    /// Lighting of the filled surface
+    lighting: Lighting,
+
+    #[arg(long, default_value_t=LIGHT_AZIMUTH_DEFAULT)]
+    /// Direction of the light around the image in degrees, 0 is along +x
+    light_azimuth: f32,
+
+    #[arg(long, default_value_t=LIGHT_ELEVATION_DEFAULT)]
+    /// Angle of the light above the image plane in degrees
+    light_elevation: f32,
+
+    #[arg(long, default_value_t=AMBIENT_DEFAULT)]
+    /// Fraction of the colour that is lit from all directions
+    ambient: f32,
+
+    #[arg(long, default_value_t=SPECULAR_DEFAULT)]
+    /// Strength of the specular highlight of phong lighting
+    specular: f32,
+
+    #[arg(long, default_value_t=SHININESS_DEFAULT)]
+    /// Sharpness of the specular highlight of phong lighting
+    shininess: f32,
+
+    #[arg(long)]
+    /// Render offscreen to this png file instead of opening a window
+    output: Option<PathBuf>,
+
+    #[arg(long, default_value_t=WIDTH_DEFAULT)]
+    /// Width in pixels of the offscreen image
+    width: u32,
+
+    #[arg(long, default_value_t=HEIGHT_DEFAULT)]
+    /// Height in pixels of the offscreen image
+    height: u32,
+
+    #[arg(long)]
+    /// Use a software (fallback) adapter instead of a GPU
+    fallback: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Write the displaced surface to a mesh file instead of viewing it
+    Export {
+        /// File name of the mesh, the format follows the extension
+        output: PathBuf,
+
+        #[arg(value_enum, short, long)]
+        /// Mesh format when it can't be told from the extension
+        format: Option<ExportFormat>,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ExportFormat {
+    /// Wavefront obj with texture coordinates and an mtl material
+    Obj,
+    /// Binary stl
+    Stl,
+    /// Binary ply with texture coordinates
+    Ply,
+    /// Binary gltf with the image embedded as the base colour texture
+    Glb,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        ExportFormat::from_str(&ext, true).ok()
+    }
 }
 
 impl Cli {
     pub fn new() -> Self { Cli::parse() }
     pub fn image_name(self: &Self) -> &PathBuf { &self.image_name }
+    pub fn display(&self) -> DisplayMode {
+        if self.wire { DisplayMode::Wire } else { self.display }
+    }
     pub fn polygon_mode(self: &Self) -> wgpu::PolygonMode {
-        if self.wire { wgpu::PolygonMode::Line }
+        if self.display() == DisplayMode::Wire { wgpu::PolygonMode::Line }
         else { wgpu::PolygonMode::Fill }
     }
-    pub fn frag_entry(self: &Self) -> &str {
-        if self.wire { "fs_wire" }
-        else { "fs_fill" }
-    }
+    pub fn frag_entry(self: &Self) -> &str { self.args().frag_entry() }
 
     pub fn channel(self: &Self) -> Channel { self.channel }
-    pub fn xres(self: &Self) -> u32 {
-        if self.xres != XRES_DEFAULT { self.xres }
-        else if self.resolution != RES_DEFAULT { self.resolution }
-        else { XRES_DEFAULT }
+    fn mix(&self) -> [f32; 4] {
+        if self.mix.is_empty() {
+            return [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 0.0];
+        }
+        if self.mix.len() > 4 {
+            log::warn!("Only the first 4 mix weights are used");
+        }
+        let mut mix = [0.0; 4];
+        let n = self.mix.len().min(4);
+        mix[..n].copy_from_slice(&self.mix[..n]);
+        mix
     }
-    pub fn yres(self: &Self) -> u32 {
-        if self.yres != YRES_DEFAULT { self.yres }
-        else if self.resolution != RES_DEFAULT { self.resolution }
-        else { YRES_DEFAULT }
+    // Vertexes in a row and number of rows for an image of dims. An x or
+    // y resolution that isn't given follows the other in proportion to the
+    // image, with neither the resolution goes to the longer side. Full and
+    // auto resolutions follow the pixels instead.
+    pub fn grid_size(&self, dims: (u32, u32)) -> (u32, u32) {
+        let resolution = match self.resolution {
+            Resolution::Count(n) => n,
+            Resolution::Full => return pixel_grid(dims, self.pixel_step),
+            Resolution::Auto => {
+                // smallest step that fits, no finer than pixel_step
+                let pixels = dims.0 as f64 * dims.1 as f64;
+                let fit = (pixels / self.max_vertices as f64).sqrt().ceil();
+                let mut step = self.pixel_step.max(fit as u32);
+                loop {
+                    let (x, y) = pixel_grid(dims, step);
+                    if x as u64 * y as u64 <= self.max_vertices as u64
+                        || step >= dims.0.max(dims.1) {
+                        return (x, y);
+                    }
+                    step += 1;
+                }
+            }
+        };
+        let width = dims.0 as f32 * self.pixel_aspect;
+        let height = dims.1 as f32;
+        // same spacing of vertexes along both sides
+        let follow = |n: u32, ratio: f32| {
+            (((n - 1) as f32 * ratio).round() as u32).max(1) + 1
+        };
+        match (self.xres != XRES_DEFAULT, self.yres != YRES_DEFAULT) {
+            (true, true) => (self.xres, self.yres),
+            (true, false) => (self.xres, follow(self.xres, height / width)),
+            (false, true) => (follow(self.yres, width / height), self.yres),
+            (false, false) if width >= height => (resolution,
+                follow(resolution, height / width)),
+            (false, false) => (follow(resolution, width / height),
+                resolution),
+        }
     }
+    pub fn pixel_aspect(&self) -> f32 { self.pixel_aspect }
+    pub fn lod(&self) -> bool { self.lod }
+    pub fn mipmaps(&self) -> bool { !self.no_mipmaps }
     pub fn args(&self) -> Args {
         Args {
-            wire: self.wire,
+            display: self.display(),
+            line_polygons: false,
             channel: self.channel,
-            xres: self.xres(),
-            yres: self.yres(),
+            mix: self.mix(),
             zoffset: self.offset,
             zscale: self.scale,
+            colormap: self.colormap,
+            contour: self.contour(),
+            overlay: Overlay {
+                color: self.wire_color,
+                opacity: self.wire_opacity.clamp(0.0, 1.0),
+            },
+            point_size: self.point_size.max(1.0),
+            sampling: self.sampling,
+            transfer: Transfer {
+                curve: self.transfer,
+                power: self.power,
+                range: self.range,
+                normalize: self.normalize,
+                invert: self.invert,
+                levels: Transfer::full_levels(self.range, self.mix()),
+            },
+            projection: self.projection,
+            light: Light {
+                lighting: self.lighting,
+                azimuth: self.light_azimuth,
+                elevation: self.light_elevation,
+                ambient: self.ambient,
+                specular: self.specular,
+                shininess: self.shininess,
+            },
         }
     }
     pub fn zoffset(&self) -> f32 { self.offset }
     pub fn zscale(&self) -> f32 { self.scale }
+    pub fn height_encoding(&self) -> Option<Encoding> { self.height_encoding }
+    pub fn colormap(&self) -> Option<Colormap> { self.colormap }
+    pub fn filters(&self) -> &[Filter] { &self.filter }
+    pub fn contour(&self) -> Contour {
+        if self.contour_levels.len() > MAX_CONTOUR_LEVELS {
+            log::warn!("Only the first {} contour levels are drawn",
+                MAX_CONTOUR_LEVELS);
+        }
+        let mut levels = [0.0; MAX_CONTOUR_LEVELS];
+        let nlevels = self.contour_levels.len().min(MAX_CONTOUR_LEVELS);
+        levels[..nlevels].copy_from_slice(&self.contour_levels[..nlevels]);
+        Contour {
+            interval: self.contour_interval.unwrap_or(0.0),
+            levels,
+            nlevels,
+            width: self.contour_width,
+            color: self.contour_color,
+        }
+    }
+    pub fn output(&self) -> Option<&PathBuf> { self.output.as_ref() }
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn fallback(&self) -> bool { self.fallback }
+    pub fn command(&self) -> Option<&Command> { self.command.as_ref() }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Default)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum, Debug, Default)]
 pub enum Channel {
+    /// Length of the colour, up to the square root of 3
     #[default]
     All = 0,
     Red = 1,
     Green = 2,
     Blue = 3,
+    /// Length of the colour, drawn in grey
     Grey = 4,
+    /// Red, green and blue layers stacked
     Rgb = 5,
+    /// Rec.709 luma of the linear colour, its relative luminance
+    Luma = 6,
+    /// CIE L* lightness, 0 to 1 rather than 0 to 100
+    Lightness = 7,
+    /// HSV hue, 0 to 1 from red through green and blue
+    Hue = 8,
+    /// HSV saturation
+    Saturation = 9,
+    /// HSV value, the largest of red, green and blue
+    Value = 10,
+    Alpha = 11,
+    /// Sum of red, green, blue and alpha weighted by --mix
+    Mix = 12,
 }
 
+pub const CHANNELS: usize = 13;
+
 impl Channel {
     pub fn value(self: &Self) -> i32 { *self as i32 }
+    pub fn from_value(value: i32) -> Self {
+        match value {
+            1 => Channel::Red,
+            2 => Channel::Green,
+            3 => Channel::Blue,
+            4 => Channel::Grey,
+            5 => Channel::Rgb,
+            6 => Channel::Luma,
+            7 => Channel::Lightness,
+            8 => Channel::Hue,
+            9 => Channel::Saturation,
+            10 => Channel::Value,
+            11 => Channel::Alpha,
+            12 => Channel::Mix,
+            _ => Channel::All,
+        }
+    }
     pub fn is_rgb(&self) -> bool { self == &Channel::Rgb }
+    // Lowest and highest value of the channel, mix its weights. Same as
+    // channel_range in the shader.
+    pub fn range(&self, mix: [f32; 4]) -> [f32; 2] {
+        match self {
+            Channel::All | Channel::Grey | Channel::Rgb => [0.0, 3.0f32.sqrt()],
+            Channel::Mix => {
+                let lo: f32 = mix.iter().map(|w| w.min(0.0)).sum();
+                let hi: f32 = mix.iter().map(|w| w.max(0.0)).sum();
+                [lo, if hi > lo { hi } else { lo + 1.0 }]
+            }
+            _ => [0.0, 1.0],
+        }
+    }
     pub fn color_writes(&self) -> wgpu::ColorWrites {
         match self {
             Channel::Red => wgpu::ColorWrites::RED,
@@ -119,26 +463,366 @@ impl Channel {
     // pub fn blue() -> i32 { Channel::Blue as i32 }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Encoding {
+    /// Values are sRGB encoded and decoded to linear, 128 is about 0.21
+    Srgb,
+    /// Values are used as they are, 128 is about 0.5
+    Linear,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Colormap {
+    /// Perceptually uniform, blue to yellow
+    Viridis,
+    /// Perceptually uniform, black to pale yellow through purple
+    Magma,
+    /// Perceptually uniform, black to pale yellow through red
+    Inferno,
+    /// Rainbow with smooth lightness
+    Turbo,
+    /// Black to white
+    Grey,
+    /// Blue to red through grey, for values either side of a middle
+    Diverging,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug, Default)]
+pub enum DisplayMode {
+    /// Filled surface
+    #[default]
+    Fill,
+    /// Grid lines only
+    Wire,
+    /// Filled surface with the grid lines drawn over it
+    Overlay,
+    /// A point at each grid vertex, nothing between them
+    Points,
+    /// A box on each quad as high as the mean of its pixels
+    Bars,
+}
+
+impl DisplayMode {
+    pub fn next(&self) -> Self {
+        match self {
+            DisplayMode::Fill => DisplayMode::Overlay,
+            DisplayMode::Overlay => DisplayMode::Wire,
+            DisplayMode::Wire => DisplayMode::Points,
+            DisplayMode::Points => DisplayMode::Bars,
+            DisplayMode::Bars => DisplayMode::Fill,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug, Default)]
+pub enum Projection {
+    /// Parallel projection, sizes don't change with distance
+    #[default]
+    Ortho,
+    /// Perspective projection through the camera's field of view
+    Perspective,
+}
+
+impl Projection {
+    pub fn toggle(&self) -> Self {
+        match self {
+            Projection::Ortho => Projection::Perspective,
+            Projection::Perspective => Projection::Ortho,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug, Default)]
+pub enum Sampling {
+    /// The closest pixel
+    Nearest = 0,
+    /// Linear between the four closest pixels
+    #[default]
+    Bilinear = 1,
+    /// Catmull-Rom cubic through the sixteen closest pixels
+    Bicubic = 2,
+}
+
+impl Sampling {
+    pub fn from_value(value: u32) -> Self {
+        match value {
+            1 => Sampling::Bilinear,
+            2 => Sampling::Bicubic,
+            _ => Sampling::Nearest,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug, Default)]
+pub enum Curve {
+    /// In proportion to the value
+    #[default]
+    Linear = 0,
+    /// Logarithmic, to bring out faint detail as astronomy images need
+    Log = 1,
+    /// The value raised to --power
+    Power = 2,
+    /// By the share of pixels below the value, histogram equalization
+    Equalize = 3,
+}
+
+impl Curve {
+    pub fn from_value(value: u32) -> Self {
+        match value {
+            1 => Curve::Log,
+            2 => Curve::Power,
+            3 => Curve::Equalize,
+            _ => Curve::Linear,
+        }
+    }
+}
+
+// How channel values map to heights, see mesh::Descriptor::transfer
+#[derive(Copy, Clone, Debug)]
+pub struct Transfer {
+    pub curve: Curve,
+    pub power: f32,
+    pub range: Option<[f32; 2]>,
+    pub normalize: bool,
+    pub invert: bool,
+    // Of each channel, from the heights when they depend on the image,
+    // see mesh::levels
+    pub levels: [crate::height::Levels; CHANNELS],
+}
+
+impl Transfer {
+    // Heights are the channel values
+    pub fn is_identity(&self) -> bool {
+        self.curve == Curve::Linear && self.range.is_none()
+            && !self.normalize && !self.invert
+    }
+    // Whether the levels need statistics of the image
+    pub fn measures(&self) -> bool {
+        self.curve == Curve::Equalize
+            || (self.normalize && self.range.is_none())
+    }
+    // The range given, or all the values a channel can take
+    fn full_levels(
+        range: Option<[f32; 2]>,
+        mix: [f32; 4],
+    ) -> [crate::height::Levels; CHANNELS] {
+        std::array::from_fn(|i| crate::height::Levels::linear(range
+            .unwrap_or(Channel::from_value(i as i32).range(mix))))
+    }
+    pub fn levels(&self, chan: Channel) -> &crate::height::Levels {
+        &self.levels[chan.value() as usize]
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug, Default)]
+pub enum Lighting {
+    /// Flat image colour
+    #[default]
+    None = 0,
+    /// Diffuse lighting
+    Lambert = 1,
+    /// Diffuse lighting with a Blinn-Phong highlight
+    Phong = 2,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub lighting: Lighting,
+    pub azimuth: f32,   // degrees
+    pub elevation: f32, // degrees
+    pub ambient: f32,
+    pub specular: f32,
+    pub shininess: f32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Resolution {
+    Count(u32), // vertexes along the longer side
+    Full,       // a vertex every pixel step
+    Auto,       // full, with a step that keeps under the vertex limit
+}
+
+impl Default for Resolution {
+    fn default() -> Self { Resolution::Count(RES_DEFAULT) }
+}
+
+impl std::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Resolution::Count(n) => write!(f, "{}", n),
+            Resolution::Full => write!(f, "full"),
+            Resolution::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<Resolution, String> {
+    match s {
+        "full" => Ok(Resolution::Full),
+        "auto" => Ok(Resolution::Auto),
+        _ => match s.parse::<u32>() {
+            Ok(n) if n >= 2 => Ok(Resolution::Count(n)),
+            _ => Err(format!(
+                "expected full, auto or a count of at least 2, got '{}'", s)),
+        },
+    }
+}
+
+// Vertexes along a side of the grid, which needs at least one quad
+fn parse_count(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(n) if n >= 2 => Ok(n),
+        _ => Err(format!("expected a count of at least 2, got '{}'", s)),
+    }
+}
+
+// Vertexes on every step pixels of the image, including the last row and
+// column. With a step of 1 vs_main loads exactly one pixel per vertex.
+fn pixel_grid(dims: (u32, u32), step: u32) -> (u32, u32) {
+    let step = step.max(1);
+    let count = |n: u32| ((n.max(1) - 1) / step + 1).max(2);
+    (count(dims.0), count(dims.1))
+}
+
+// MIN,MAX with MIN below MAX
+fn parse_range(s: &str) -> Result<[f32; 2], String> {
+    let error = || format!("expected MIN,MAX with MIN below MAX, got '{}'", s);
+    let (min, max) = s.split_once(',').ok_or_else(error)?;
+    match (min.trim().parse::<f32>(), max.trim().parse::<f32>()) {
+        (Ok(min), Ok(max)) if min < max => Ok([min, max]),
+        _ => Err(error()),
+    }
+}
+
+// Exponent above 0, at 0 every height would be the top and below 0 they
+// would go past it
+fn parse_power(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(power) if power > 0.0 && power.is_finite() => Ok(power),
+        _ => Err(format!("expected a power above 0, got '{}'", s)),
+    }
+}
+
+// sRGB hex colour, with or without a leading #, to linear rgb
+fn parse_color(s: &str) -> Result<[f32; 3], String> {
+    let hex = s.trim_start_matches('#');
+    let rgb = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or(format!("expected a colour as rrggbb, got '{}'", s))?;
+    Ok([16, 8, 0].map(|shift| crate::height::srgb_to_linear(
+        ((rgb >> shift) & 0xff) as f32 / 255.0)))
+}
+
+// Filters of the heights, see filter::apply
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Filter {
+    Blur(f32),         // Gaussian of this sigma
+    Median(u32),       // over a square of this radius
+    Unsharp(f32, f32), // sigma of the blur and amount of the difference
+    Open(u32),         // erosion then dilation, removes peaks
+    Close(u32),        // dilation then erosion, fills pits
+    Sobel,             // gradient magnitude
+}
+
+// name[:size[:amount]], the size defaulting to a pixel
+fn parse_filter(s: &str) -> Result<Filter, String> {
+    let error = || format!("expected blur, median, unsharp, open, close or \
+        sobel, with a size as in blur:2, got '{}'", s);
+    let mut parts = s.split(':');
+    let name = parts.next().unwrap_or_default();
+    let params = parts.map(|p| p.parse::<f32>().ok().filter(|v| *v >= 0.0))
+        .collect::<Option<Vec<f32>>>().ok_or_else(error)?;
+    let size = params.first().copied().unwrap_or(1.0);
+    let radius = size.round() as u32;
+    let filter = match (name, params.len()) {
+        ("blur", 0 | 1) => Filter::Blur(size),
+        ("median", 0 | 1) => Filter::Median(radius),
+        ("unsharp", 0..=2) =>
+            Filter::Unsharp(size, params.get(1).copied().unwrap_or(1.0)),
+        ("open", 0 | 1) => Filter::Open(radius),
+        ("close", 0 | 1) => Filter::Close(radius),
+        ("sobel", 0) => Filter::Sobel,
+        _ => return Err(error()),
+    };
+    Ok(filter)
+}
+
+// Lines over the filled surface, at every multiple of interval (0 for
+// none) and at each of the given levels.
+#[derive(Copy, Clone, Debug)]
+pub struct Contour {
+    pub interval: f32,
+    pub levels: [f32; MAX_CONTOUR_LEVELS],
+    pub nlevels: usize,
+    pub width: f32,      // pixels
+    pub color: [f32; 3], // linear
+}
+
+// Grid lines over the filled surface
+#[derive(Copy, Clone, Debug)]
+pub struct Overlay {
+    pub color: [f32; 3], // linear
+    pub opacity: f32,
+}
+
 #[derive(Copy,Clone)]
 pub struct Args {
-    wire: bool,
+    pub display: DisplayMode,
+    // The device draws PolygonMode::Line, else the wire frame is shaded
+    pub line_polygons: bool,
     pub channel: Channel,
-    pub xres: u32,
-    pub yres: u32,
+    pub mix: [f32; 4], // weights of the mix channel
     pub zoffset: f32,
     pub zscale: f32,
+    pub colormap: Option<Colormap>,
+    pub contour: Contour,
+    pub overlay: Overlay,
+    pub point_size: f32, // pixels
+    pub sampling: Sampling,
+    pub transfer: Transfer,
+    pub projection: Projection,
+    pub light: Light,
 }
 
 impl Args {
     pub fn channel(self: &Self) -> Channel { self.channel }
     pub fn polygon_mode(self: &Self) -> wgpu::PolygonMode {
-        if self.wire { wgpu::PolygonMode::Line }
+        if self.wire() && self.line_polygons { wgpu::PolygonMode::Line }
         else { wgpu::PolygonMode::Fill }
     }
-    pub fn frag_entry(self: &Self) -> &str {
-        if self.wire { "fs_wire" }
+    pub fn frag_entry(self: &Self) -> &'static str {
+        if self.wire() && self.line_polygons { "fs_wire" }
+        else if self.wire() { "fs_wire_grid" }
+        else if self.colormap.is_some() { "fs_colormap" }
         else { "fs_fill" }
     }
+    fn wire(&self) -> bool { self.display == DisplayMode::Wire }
+    // Points a pixel wide are the device's own, larger ones are two
+    // triangles.
+    pub fn geometry(&self) -> crate::mesh::Geometry {
+        use crate::mesh::Geometry;
+        match self.display {
+            DisplayMode::Points if self.point_size <= 1.0 => Geometry::Points(1),
+            DisplayMode::Points => Geometry::Points(6),
+            DisplayMode::Bars => Geometry::Bars,
+            _ => Geometry::Surface,
+        }
+    }
+    pub fn topology(&self) -> wgpu::PrimitiveTopology {
+        use crate::mesh::Geometry;
+        match self.geometry() {
+            Geometry::Surface => crate::mesh::TOPOLOGY,
+            Geometry::Points(1) => wgpu::PrimitiveTopology::PointList,
+            _ => wgpu::PrimitiveTopology::TriangleList,
+        }
+    }
+    // A colormap colours every channel, otherwise each layer of Rgb only
+    // writes its own.
+    pub fn color_writes(&self, chan: Channel) -> wgpu::ColorWrites {
+        if self.colormap.is_some() && !self.wire() { wgpu::ColorWrites::ALL }
+        else { chan.color_writes() }
+    }
 }
 
 
@@ -213,3 +897,19 @@ impl Args {
             // Channel::Red => wgpu::ColorWrites::RED,
             // Channel::Green => wgpu::ColorWrites::GREEN,
             // Channel::Blue => wgpu::ColorWrites::BLUE,
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_range_spans_the_negative_and_positive_weights() {
+        let range = |mix| Channel::Mix.range(mix);
+        assert_eq!(range([1.0, -1.0, 0.0, 0.0]), [-1.0, 1.0]);
+        assert_eq!(range([0.5, -0.25, 0.5, -0.5]), [-0.75, 1.0]);
+        // all negative, the top is black
+        assert_eq!(range([-0.5, -0.25, 0.0, 0.0]), [-0.75, 0.0]);
+        // no weights, still a range to stretch
+        assert_eq!(range([0.0; 4]), [0.0, 1.0]);
+    }
+}
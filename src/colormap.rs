@@ -0,0 +1,69 @@
+// Colour lookup tables for colouring the surface by height.
+use crate::cli::Colormap;
+
+pub const LUT_SIZE: usize = 256;
+
+// Samples of the matplotlib maps at ten even steps, in sRGB.
+const VIRIDIS: [u32; 10] = [
+    0x440154, 0x482878, 0x3e4989, 0x31688e, 0x26828e,
+    0x1f9e89, 0x35b779, 0x6ece58, 0xb5de2b, 0xfde725,
+];
+const MAGMA: [u32; 10] = [
+    0x000004, 0x180f3d, 0x440f76, 0x721f81, 0x9e2f7f,
+    0xcd4071, 0xf1605d, 0xfd9668, 0xfeca8d, 0xfcfdbf,
+];
+const INFERNO: [u32; 10] = [
+    0x000004, 0x1b0c41, 0x4a0c6b, 0x781c6d, 0xa52c60,
+    0xcf4446, 0xed6925, 0xfb9b06, 0xf7d13d, 0xfcffa4,
+];
+// Moreland's cool to warm
+const DIVERGING: [u32; 3] = [0x3b4cc0, 0xdddddd, 0xb40426];
+const GREY: [u32; 2] = [0x000000, 0xffffff];
+
+// sRGB colours of the map from low to high.
+pub fn lut(map: Colormap) -> Vec<[u8; 4]> {
+    (0..LUT_SIZE).map(|i| {
+        let t = i as f32 / (LUT_SIZE - 1) as f32;
+        let rgb = match map {
+            Colormap::Viridis => interpolate(&VIRIDIS, t),
+            Colormap::Magma => interpolate(&MAGMA, t),
+            Colormap::Inferno => interpolate(&INFERNO, t),
+            Colormap::Turbo => turbo(t),
+            Colormap::Grey => interpolate(&GREY, t),
+            Colormap::Diverging => interpolate(&DIVERGING, t),
+        };
+        let [r, g, b] = rgb.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        [r, g, b, 255]
+    }).collect()
+}
+
+// Linear between evenly spaced samples
+fn interpolate(samples: &[u32], t: f32) -> [f32; 3] {
+    let x = t * (samples.len() - 1) as f32;
+    let i = (x.floor() as usize).min(samples.len() - 2);
+    let f = x - i as f32;
+    let (a, b) = (unpack(samples[i]), unpack(samples[i + 1]));
+    [0, 1, 2].map(|k| a[k] + (b[k] - a[k]) * f)
+}
+
+fn unpack(rgb: u32) -> [f32; 3] {
+    [16, 8, 0].map(|shift| ((rgb >> shift) & 0xff) as f32 / 255.0)
+}
+
+// Polynomial fit of Google's turbo map, in f64 as the coefficients are
+// published.
+fn turbo(t: f32) -> [f32; 3] {
+    let t = t as f64;
+    let poly = |c: [f64; 6]| {
+        (c[0] + t * (c[1] + t * (c[2] + t * (c[3] + t * (c[4] + t * c[5])))))
+            as f32
+    };
+    [
+        poly([0.13572138, 4.61539260, -42.66032258, 132.13108234,
+            -152.94239396, 59.28637943]),
+        poly([0.09140261, 2.19418839, 4.84296658, -14.18503333,
+            4.27729857, 2.82956604]),
+        poly([0.10667330, 12.64194608, -60.58204836, 110.36276771,
+            -89.90310912, 27.34824973]),
+    ]
+}
@@ -0,0 +1,77 @@
+// Errors reported to the user. main prints them and exits with a non-zero
+// status rather than panicking.
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    // The image file is missing or can't be read
+    Open(PathBuf, std::io::Error),
+    // Not a format the image crate was built with
+    Unsupported(PathBuf),
+    // A known format that fails to decode
+    Decode(PathBuf, image::ImageError),
+    // An image or texture past a decoder or device limit
+    TooLarge { name: String, dims: (u32, u32), max: Option<u32> },
+    NoAdapter,
+    Surface(wgpu::CreateSurfaceError),
+    Device(wgpu::RequestDeviceError),
+    Window(winit::error::OsError),
+    Readback(wgpu::BufferAsyncError),
+    // Writing the rendered frame
+    Save(PathBuf, image::ImageError),
+    // No mesh format given and none known for the extension
+    MeshFormat(PathBuf),
+    Export(PathBuf, anyhow::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Open(path, err) =>
+                write!(f, "cannot open {}: {}", path.display(), err),
+            Error::Unsupported(path) => write!(f,
+                "{}: unsupported image format, expected png, jpeg or tiff",
+                path.display()),
+            Error::Decode(path, err) =>
+                write!(f, "cannot decode {}: {}", path.display(), err),
+            Error::TooLarge { name, dims, max: Some(max) } => write!(f,
+                "{} is {}x{}, larger than the {} texels the device allows",
+                name, dims.0, dims.1, max),
+            Error::TooLarge { name, dims, max: None } => write!(f,
+                "{} is {}x{}, too large to decode", name, dims.0, dims.1),
+            Error::NoAdapter => write!(f,
+                "no graphics adapter found, try --fallback for a software one"),
+            Error::Surface(err) =>
+                write!(f, "cannot create a surface for the window: {}", err),
+            Error::Device(err) =>
+                write!(f, "cannot open the graphics device: {}", err),
+            Error::Window(err) => write!(f, "cannot open a window: {}", err),
+            Error::Readback(err) =>
+                write!(f, "cannot read back the frame: {}", err),
+            Error::Save(path, err) =>
+                write!(f, "cannot write {}: {}", path.display(), err),
+            Error::MeshFormat(path) => write!(f,
+                "unknown mesh format for {}, use --format", path.display()),
+            Error::Export(path, err) =>
+                write!(f, "cannot export {}: {:#}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Open(_, err) => Some(err),
+            Error::Decode(_, err) | Error::Save(_, err) => Some(err),
+            Error::Surface(err) => Some(err),
+            Error::Device(err) => Some(err),
+            Error::Window(err) => Some(err),
+            Error::Readback(err) => Some(err),
+            Error::Export(_, err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
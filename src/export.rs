@@ -0,0 +1,297 @@
+// Writes the displaced surface to mesh files for printing and modelling.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::*;
+
+use crate::cli::{Channel, ExportFormat};
+use crate::mesh;
+
+// One surface, Rgb mode exports a layer per colour channel.
+pub struct Layer {
+    pub name: String,
+    pub channel: Channel,
+    pub grid: mesh::Grid,
+}
+
+pub fn write(
+    path: &Path,
+    format: ExportFormat,
+    layers: &[Layer],
+    image_name: &Path,
+    image: &image::DynamicImage,
+) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?);
+    match format {
+        ExportFormat::Obj => {
+            let mtl = path.with_extension("mtl");
+            write_mtl(&mtl, image_name)?;
+            write_obj(&mut out, layers, &mtl)?;
+        }
+        ExportFormat::Stl => write_stl(&mut out, layers)?,
+        ExportFormat::Ply => write_ply(&mut out, layers)?,
+        ExportFormat::Glb => write_glb(&mut out, layers, image)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// Material referencing the source image so the surface keeps its colour.
+fn write_mtl(path: &Path, image_name: &Path) -> Result<()> {
+    let image = image_name.canonicalize()
+        .unwrap_or_else(|_| image_name.to_path_buf());
+    let mut out = BufWriter::new(File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?);
+    writeln!(out, "newmtl image")?;
+    writeln!(out, "Ka 1.0 1.0 1.0")?;
+    writeln!(out, "Kd 1.0 1.0 1.0")?;
+    writeln!(out, "map_Kd {}", image.display())?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_obj(out: &mut impl Write, layers: &[Layer], mtl: &Path) -> Result<()> {
+    writeln!(out, "# image_view surface")?;
+    if let Some(name) = mtl.file_name() {
+        writeln!(out, "mtllib {}", name.to_string_lossy())?;
+    }
+    // obj indexes from 1 and across the whole file
+    let mut base = 1;
+    for layer in layers {
+        let grid = &layer.grid;
+        writeln!(out, "o {}", layer.name)?;
+        for [x, y, z] in &grid.positions {
+            writeln!(out, "v {} {} {}", x, y, z)?;
+        }
+        // obj texture coordinates have v pointing up
+        for [u, v] in &grid.uvs {
+            writeln!(out, "vt {} {}", u, 1.0 - v)?;
+        }
+        writeln!(out, "usemtl image")?;
+        for [a, b, c] in &grid.triangles {
+            let (a, b, c) = (a + base, b + base, c + base);
+            writeln!(out, "f {a}/{a} {b}/{b} {c}/{c}")?;
+        }
+        base += grid.positions.len() as u32;
+    }
+    Ok(())
+}
+
+fn write_stl(out: &mut impl Write, layers: &[Layer]) -> Result<()> {
+    let mut header = [0u8; 80];
+    let title = b"image_view surface";
+    header[..title.len()].copy_from_slice(title);
+    out.write_all(&header)?;
+    let count: usize = layers.iter().map(|l| l.grid.triangles.len()).sum();
+    out.write_all(&(count as u32).to_le_bytes())?;
+    for layer in layers {
+        let grid = &layer.grid;
+        for triangle in &grid.triangles {
+            let [a, b, c] = triangle.map(|i| grid.positions[i as usize]);
+            for value in normal(a, b, c).iter().chain(&a).chain(&b).chain(&c) {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&0u16.to_le_bytes())?; // attribute byte count
+        }
+    }
+    Ok(())
+}
+
+fn write_ply(out: &mut impl Write, layers: &[Layer]) -> Result<()> {
+    let nverts: usize = layers.iter().map(|l| l.grid.positions.len()).sum();
+    let nfaces: usize = layers.iter().map(|l| l.grid.triangles.len()).sum();
+    writeln!(out, "ply")?;
+    writeln!(out, "format binary_little_endian 1.0")?;
+    writeln!(out, "comment image_view surface")?;
+    writeln!(out, "element vertex {}", nverts)?;
+    writeln!(out, "property float x")?;
+    writeln!(out, "property float y")?;
+    writeln!(out, "property float z")?;
+    writeln!(out, "property float s")?;
+    writeln!(out, "property float t")?;
+    writeln!(out, "element face {}", nfaces)?;
+    writeln!(out, "property list uchar uint vertex_indices")?;
+    writeln!(out, "end_header")?;
+    for layer in layers {
+        let grid = &layer.grid;
+        for (position, [u, v]) in grid.positions.iter().zip(&grid.uvs) {
+            for value in position.iter().chain(&[*u, 1.0 - v]) {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    let mut base = 0;
+    for layer in layers {
+        let grid = &layer.grid;
+        for triangle in &grid.triangles {
+            out.write_all(&[3u8])?;
+            for index in triangle {
+                out.write_all(&(index + base).to_le_bytes())?;
+            }
+        }
+        base += grid.positions.len() as u32;
+    }
+    Ok(())
+}
+
+// A node per layer, each with its own mesh and a material that takes the
+// base colour from the embedded image. For red, green and blue the factor
+// keeps only the layer's channel, as fs_fill does. The other channels,
+// which fs_fill draws in grey or by hue, keep the colours of the image as
+// a factor can't express those.
+fn write_glb(
+    out: &mut impl Write,
+    layers: &[Layer],
+    image: &image::DynamicImage,
+) -> Result<()> {
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+
+    let mut bin: Vec<u8> = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+
+    // Adds a 4 byte aligned buffer view and returns its index.
+    let mut push_view = |bin: &mut Vec<u8>, bytes: &[u8], target: Option<u32>| {
+        let offset = bin.len();
+        bin.extend_from_slice(bytes);
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        views.push(match target {
+            Some(target) => format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                offset, bytes.len(), target),
+            None => format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{}}}"#,
+                offset, bytes.len()),
+        });
+        views.len() - 1
+    };
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    let image_view = push_view(&mut bin, png.get_ref(), None);
+
+    let mut meshes = Vec::new();
+    let mut materials = Vec::new();
+    let mut nodes = Vec::new();
+    for layer in layers {
+        let grid = &layer.grid;
+        let normals = vertex_normals(grid);
+
+        let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+        for position in &grid.positions {
+            for i in 0..3 {
+                min[i] = min[i].min(position[i]);
+                max[i] = max[i].max(position[i]);
+            }
+        }
+        let count = grid.positions.len();
+
+        let view = push_view(&mut bin,
+            bytemuck::cast_slice(&grid.positions), Some(ARRAY_BUFFER));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            view, FLOAT, count, min[0], min[1], min[2], max[0], max[1], max[2]));
+        let position = accessors.len() - 1;
+
+        let view = push_view(&mut bin,
+            bytemuck::cast_slice(&normals), Some(ARRAY_BUFFER));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC3"}}"#,
+            view, FLOAT, count));
+        let normal = accessors.len() - 1;
+
+        // gltf texture coordinates have v pointing down, as in wgpu
+        let view = push_view(&mut bin,
+            bytemuck::cast_slice(&grid.uvs), Some(ARRAY_BUFFER));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC2"}}"#,
+            view, FLOAT, count));
+        let texcoord = accessors.len() - 1;
+
+        let view = push_view(&mut bin,
+            bytemuck::cast_slice(&grid.triangles), Some(ELEMENT_ARRAY_BUFFER));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"SCALAR"}}"#,
+            view, UNSIGNED_INT, grid.triangles.len() * 3));
+        let indices = accessors.len() - 1;
+
+        let factor = match layer.channel {
+            Channel::Red => [1.0, 0.0, 0.0],
+            Channel::Green => [0.0, 1.0, 0.0],
+            Channel::Blue => [0.0, 0.0, 1.0],
+            _ => [1.0, 1.0, 1.0],
+        };
+        materials.push(format!(
+            r#"{{"name":"{}","doubleSided":true,"pbrMetallicRoughness":{{"baseColorTexture":{{"index":0}},"baseColorFactor":[{},{},{},1.0],"metallicFactor":0.0,"roughnessFactor":1.0}}}}"#,
+            layer.name, factor[0], factor[1], factor[2]));
+        meshes.push(format!(
+            r#"{{"name":"{}","primitives":[{{"attributes":{{"POSITION":{},"NORMAL":{},"TEXCOORD_0":{}}},"indices":{},"material":{}}}]}}"#,
+            layer.name, position, normal, texcoord, indices,
+            materials.len() - 1));
+        nodes.push(format!(r#"{{"name":"{}","mesh":{}}}"#,
+            layer.name, meshes.len() - 1));
+    }
+
+    // Same filtering and wrapping as the sampler of the colour texture
+    let json = format!(
+        concat!(
+            r#"{{"asset":{{"version":"2.0","generator":"image_view"}},"#,
+            r#""scene":0,"scenes":[{{"nodes":[{}]}}],"#,
+            r#""nodes":[{}],"meshes":[{}],"materials":[{}],"#,
+            r#""textures":[{{"sampler":0,"source":0}}],"#,
+            r#""images":[{{"bufferView":{},"mimeType":"image/png"}}],"#,
+            r#""samplers":[{{"magFilter":9729,"minFilter":9728,"wrapS":10497,"wrapT":10497}}],"#,
+            r#""accessors":[{}],"bufferViews":[{}],"#,
+            r#""buffers":[{{"byteLength":{}}}]}}"#,
+        ),
+        (0..nodes.len()).map(|i| i.to_string()).collect::<Vec<_>>().join(","),
+        nodes.join(","), meshes.join(","), materials.join(","),
+        image_view, accessors.join(","), views.join(","), bin.len(),
+    );
+    let mut json = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    // 12 byte header then the json and binary chunks
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+    out.write_all(b"glTF")?;
+    out.write_all(&2u32.to_le_bytes())?;
+    out.write_all(&(length as u32).to_le_bytes())?;
+    out.write_all(&(json.len() as u32).to_le_bytes())?;
+    out.write_all(b"JSON")?;
+    out.write_all(&json)?;
+    out.write_all(&(bin.len() as u32).to_le_bytes())?;
+    out.write_all(b"BIN\0")?;
+    out.write_all(&bin)?;
+    Ok(())
+}
+
+// Area weighted average of the normals of the triangles around each vertex.
+fn vertex_normals(grid: &mesh::Grid) -> Vec<[f32; 3]> {
+    use cgmath::InnerSpace;
+    let mut normals = vec![cgmath::Vector3::new(0.0f32, 0.0, 0.0);
+        grid.positions.len()];
+    for triangle in &grid.triangles {
+        let [a, b, c] = triangle.map(|i|
+            cgmath::Vector3::from(grid.positions[i as usize]));
+        let n = (b - a).cross(c - a);
+        for i in triangle {
+            normals[*i as usize] += n;
+        }
+    }
+    normals.into_iter().map(|n|
+        if n.magnitude2() > 0.0 { n.normalize().into() } else { [0.0, 0.0, 1.0] }
+    ).collect()
+}
+
+fn normal(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> [f32; 3] {
+    use cgmath::InnerSpace;
+    let a = cgmath::Vector3::from(a);
+    let n = (cgmath::Vector3::from(b) - a)
+        .cross(cgmath::Vector3::from(c) - a);
+    if n.magnitude2() > 0.0 { n.normalize().into() } else { [0.0; 3] }
+}
@@ -0,0 +1,137 @@
+// Filters run on the heights before they are uploaded, in the order given
+// with --filter. The image texture keeps the decoded colours. Sizes are in
+// pixels of the image, a field with a pixel every scale pixels of the
+// image, as a pyramid level has, gets them scaled down to match.
+use crate::cli::Filter;
+use crate::height::Field;
+
+pub fn apply(field: Field, filters: &[Filter], scale: f32) -> Field {
+    filters.iter().fold(field, |field, filter| match *filter {
+        Filter::Blur(sigma) => blur(&field, sigma / scale),
+        Filter::Median(radius) => median(&field, scaled(radius, scale)),
+        Filter::Unsharp(sigma, amount) => {
+            let blurred = blur(&field, sigma / scale);
+            map(&field, |x, y| {
+                let (c, b) = (field.load(x, y), blurred.load(x, y));
+                [0, 1, 2, 3].map(|k| c[k] + amount * (c[k] - b[k]))
+            })
+        }
+        Filter::Open(radius) => {
+            let radius = scaled(radius, scale);
+            extreme(&extreme(&field, radius, f32::min), radius, f32::max)
+        }
+        Filter::Close(radius) => {
+            let radius = scaled(radius, scale);
+            extreme(&extreme(&field, radius, f32::max), radius, f32::min)
+        }
+        Filter::Sobel => sobel(&field, scale),
+    })
+}
+
+// Pixels beyond a part of the field that the filters read, so a part
+// filtered on its own with this margin matches the whole.
+pub fn reach(filters: &[Filter], scale: f32) -> u32 {
+    filters.iter().map(|filter| match *filter {
+        Filter::Blur(sigma) | Filter::Unsharp(sigma, _) =>
+            radius(sigma / scale),
+        Filter::Median(radius) => scaled(radius, scale),
+        Filter::Open(radius) | Filter::Close(radius) =>
+            2 * scaled(radius, scale),
+        Filter::Sobel => 1,
+    }).sum()
+}
+
+fn scaled(radius: u32, scale: f32) -> u32 {
+    (radius as f32 / scale).round() as u32
+}
+
+// Of the Gaussian kernel, past which the weights are negligible
+fn radius(sigma: f32) -> u32 {
+    (3.0 * sigma).ceil().max(0.0) as u32
+}
+
+// A new value for each pixel from the pixels around it
+fn map(field: &Field, mut f: impl FnMut(i32, i32) -> [f32; 4]) -> Field {
+    let (width, height) = field.dimensions();
+    let texels = (0..height as i32)
+        .flat_map(|y| (0..width as i32).map(move |x| (x, y)))
+        .map(|(x, y)| f(x, y))
+        .collect();
+    field.with_texels(texels)
+}
+
+// Separable, a pass along the rows then one down the columns
+fn blur(field: &Field, sigma: f32) -> Field {
+    let r = radius(sigma) as i32;
+    if r == 0 {
+        return field.clone();
+    }
+    let weights: Vec<f32> = (-r..=r)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let pass = |field: &Field, (dx, dy): (i32, i32)| map(field, |x, y| {
+        let mut sum = [0.0; 4];
+        for (i, w) in (-r..=r).zip(&weights) {
+            let t = field.load(x + i * dx, y + i * dy);
+            for k in 0..4 { sum[k] += t[k] * w / total; }
+        }
+        sum
+    });
+    pass(&pass(field, (1, 0)), (0, 1))
+}
+
+// Of each channel over a square, which removes specks a blur would smear
+fn median(field: &Field, radius: u32) -> Field {
+    let r = radius as i32;
+    if r == 0 {
+        return field.clone();
+    }
+    // one scratch buffer for every pixel and channel
+    let mut values: Vec<f32> = Vec::with_capacity(
+        ((2 * r + 1) * (2 * r + 1)) as usize);
+    map(field, |x, y| {
+        [0, 1, 2, 3].map(|k| {
+            values.clear();
+            for j in -r..=r {
+                for i in -r..=r {
+                    values.push(field.load(x + i, y + j)[k]);
+                }
+            }
+            let mid = values.len() / 2;
+            *values.select_nth_unstable_by(mid, f32::total_cmp).1
+        })
+    })
+}
+
+// Erosion with f32::min, dilation with f32::max, over a square. A square
+// is separable, so this is the extreme along the rows then the columns.
+fn extreme(field: &Field, radius: u32, pick: fn(f32, f32) -> f32) -> Field {
+    let r = radius as i32;
+    if r == 0 {
+        return field.clone();
+    }
+    let pass = |field: &Field, (dx, dy): (i32, i32)| map(field, |x, y| {
+        (-r..=r).map(|i| field.load(x + i * dx, y + i * dy))
+            .reduce(|a, b| [0, 1, 2, 3].map(|k| pick(a[k], b[k])))
+            .unwrap()
+    });
+    pass(&pass(field, (1, 0)), (0, 1))
+}
+
+// Magnitude of the gradient of each channel with the usual 3x3 weights,
+// eight times the slope per pixel of the image. Alpha is kept.
+fn sobel(field: &Field, scale: f32) -> Field {
+    map(field, |x, y| {
+        let at = |i: i32, j: i32| field.load(x + i, y + j);
+        let mut magnitude = at(0, 0);
+        for (k, m) in magnitude.iter_mut().enumerate().take(3) {
+            let gx = at(1, -1)[k] + 2.0 * at(1, 0)[k] + at(1, 1)[k]
+                - at(-1, -1)[k] - 2.0 * at(-1, 0)[k] - at(-1, 1)[k];
+            let gy = at(-1, 1)[k] + 2.0 * at(0, 1)[k] + at(1, 1)[k]
+                - at(-1, -1)[k] - 2.0 * at(0, -1)[k] - at(1, -1)[k];
+            *m = (gx * gx + gy * gy).sqrt() / scale;
+        }
+        magnitude
+    })
+}
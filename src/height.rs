@@ -0,0 +1,394 @@
+// CPU side copy of the height texture, holding the values the vertex
+// shader reads with textureLoad.
+use image::GenericImageView;
+
+use crate::cli::{Encoding, Sampling};
+
+// Steps of the cumulative histogram used for equalization
+pub const CDF_BINS: usize = 64;
+
+// The channel values mapped onto the full height, and the share of the
+// pixels at or below each of CDF_BINS values evenly across them.
+#[derive(Copy, Clone, Debug)]
+pub struct Levels {
+    pub lo: f32,
+    pub hi: f32,
+    pub cdf: [f32; CDF_BINS],
+}
+
+impl Levels {
+    // Without statistics, an even spread of values
+    pub fn linear(range: [f32; 2]) -> Self {
+        Self {
+            lo: range[0],
+            hi: range[1],
+            cdf: std::array::from_fn(|i| i as f32 / (CDF_BINS - 1) as f32),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Field {
+    width: u32,
+    height: u32,
+    bits: u32,             // bits per channel of the source image
+    grey: bool,            // the source has a single channel, no alpha
+    encoding: Encoding,    // how the source values were decoded
+    texels: Vec<[f32; 4]>, // row major, top row first
+}
+
+impl Field {
+    // sRGB encoded colour channels are decoded to linear, as Rgba8UnormSrgb
+    // is when loaded in the shader. Without an encoding 8 bit images are
+    // taken to be sRGB, deeper images are measurements more often than
+    // pictures so are taken to be linear. Alpha is always linear.
+    pub fn from_image(
+        img: &image::DynamicImage,
+        encoding: Option<Encoding>,
+    ) -> Self {
+        let (width, height) = img.dimensions();
+        let color = img.color();
+        let bits = 8 * (color.bytes_per_pixel() / color.channel_count()) as u32;
+        let grey = color.channel_count() == 1;
+        let encoding = encoding.unwrap_or(
+            if bits == 8 { Encoding::Srgb } else { Encoding::Linear });
+        let texels: Vec<[f32; 4]> = match bits {
+            8 => img.to_rgba8().pixels()
+                .map(|p| p.0.map(|c| c as f32 / 255.0))
+                .collect(),
+            16 => img.to_rgba16().pixels()
+                .map(|p| p.0.map(|c| c as f32 / 65535.0))
+                .collect(),
+            _ => img.to_rgba32f().pixels().map(|p| p.0).collect(),
+        };
+        let texels = match encoding {
+            Encoding::Linear => texels,
+            Encoding::Srgb => texels.into_iter().map(|t| [
+                srgb_to_linear(t[0]),
+                srgb_to_linear(t[1]),
+                srgb_to_linear(t[2]),
+                t[3],
+            ]).collect(),
+        };
+        Self { width, height, bits, grey, encoding, texels }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) { (self.width, self.height) }
+    pub fn bits(&self) -> u32 { self.bits }
+    pub fn grey(&self) -> bool { self.grey }
+    pub fn encoding(&self) -> Encoding { self.encoding }
+    pub fn texels(&self) -> &[[f32; 4]] { &self.texels }
+
+    // Levels of a full mipmap chain, down to a single pixel
+    pub fn mip_levels(&self) -> u32 {
+        32 - self.width.max(self.height).leading_zeros()
+    }
+
+    // The next mip level, each pixel the mean of two by two. An odd last
+    // row or column is left out, as the GPU sizes its mip levels.
+    pub fn halve(&self) -> Field {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let mut sum = [0.0; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let t = self.load(2 * x + dx, 2 * y + dy);
+                    for k in 0..4 { sum[k] += t[k] / 4.0; }
+                }
+                texels.push(sum);
+            }
+        }
+        Field { width, height, texels, ..*self }
+    }
+
+    // Mip level of the field, level 0 is the field itself
+    pub fn level(&self, level: u32) -> std::borrow::Cow<'_, Field> {
+        let mut field = std::borrow::Cow::Borrowed(self);
+        for _ in 0..level {
+            field = std::borrow::Cow::Owned(field.halve());
+        }
+        field
+    }
+
+    // The same field with new values, as the filters make
+    pub fn with_texels(&self, texels: Vec<[f32; 4]>) -> Field {
+        assert_eq!(texels.len(), self.texels.len());
+        Field { texels, ..*self }
+    }
+
+    // The part of the field from x, y, clipped to its edges as
+    // DynamicImage::crop_imm is.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Field {
+        let x = x.min(self.width);
+        let y = y.min(self.height);
+        let width = width.min(self.width - x);
+        let height = height.min(self.height - y);
+        let texels = (y..y + height)
+            .flat_map(|row| {
+                let start = (row * self.width + x) as usize;
+                self.texels[start..start + width as usize].iter().copied()
+            })
+            .collect();
+        Field { width, height, texels, ..*self }
+    }
+
+    // Levels of the values over range, over the range of the values in
+    // the field without one.
+    pub fn levels(
+        &self,
+        value: impl Fn([f32; 4]) -> f32,
+        range: Option<[f32; 2]>,
+    ) -> Levels {
+        let [lo, hi] = range.unwrap_or_else(|| self.texels.iter()
+            .map(|t| value(*t))
+            .fold([f32::MAX, f32::MIN], |[lo, hi], v| [lo.min(v), hi.max(v)]));
+        // a flat field has all its values at the bottom
+        let hi = if hi > lo { hi } else { lo + 1.0 };
+        let steps = (CDF_BINS - 1) as f32;
+        let mut counts = [0u64; CDF_BINS];
+        for t in &self.texels {
+            let x = ((value(*t) - lo) / (hi - lo)).clamp(0.0, 1.0);
+            counts[(x * steps).ceil() as usize] += 1;
+        }
+        let mut below = 0;
+        let cdf = counts.map(|count| {
+            below += count;
+            below as f32 / self.texels.len().max(1) as f32
+        });
+        Levels { lo, hi, cdf }
+    }
+
+    // Same as textureLoad, except that out of range coordinates are
+    // clamped to the edge.
+    pub fn load(&self, x: i32, y: i32) -> [f32; 4] {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.texels[y * self.width as usize + x]
+    }
+
+    // The value at a position in pixels, filtered as sample_value in the
+    // shader. The values of the pixels are filtered, not their colours.
+    pub fn sample(
+        &self,
+        pixel: (f32, f32),
+        sampling: Sampling,
+        value: impl Fn([f32; 4]) -> f32,
+    ) -> f32 {
+        let at = |x: f32, y: f32| value(self.load(x as i32, y as i32));
+        let (x, y) = pixel;
+        match sampling {
+            Sampling::Nearest => at((x + 0.5).floor(), (y + 0.5).floor()),
+            Sampling::Bilinear => {
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+                lerp(lerp(at(x0, y0), at(x0 + 1.0, y0), fx),
+                    lerp(at(x0, y0 + 1.0), at(x0 + 1.0, y0 + 1.0), fx), fy)
+            }
+            Sampling::Bicubic => {
+                let (x0, y0) = (x.floor(), y.floor());
+                let wx = catmull_rom(x - x0);
+                let wy = catmull_rom(y - y0);
+                let mut sum = 0.0;
+                for (j, wy) in wy.iter().enumerate() {
+                    for (i, wx) in wx.iter().enumerate() {
+                        sum += wx * wy * at(x0 + i as f32 - 1.0,
+                            y0 + j as f32 - 1.0);
+                    }
+                }
+                sum
+            }
+        }
+    }
+}
+
+// Rec.709 weights of linear rgb, the relative luminance Y
+pub fn luma(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+// CIE L* of a relative luminance, scaled to 0 to 1
+pub fn lightness(y: f32) -> f32 {
+    // (6/29)^3 and (29/3)^3
+    const EPSILON: f32 = 216.0 / 24389.0;
+    const KAPPA: f32 = 24389.0 / 27.0;
+    if y > EPSILON { 1.16 * y.cbrt() - 0.16 } else { KAPPA * y / 100.0 }
+}
+
+// Hue, saturation and value. Hue is 0 to 1 from red through green and
+// blue, 0 for greys.
+pub fn hsv(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb;
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta <= 0.0 { 0.0 }
+        else if max == r { (g - b) / delta }
+        else if max == g { (b - r) / delta + 2.0 }
+        else { (r - g) / delta + 4.0 };
+    let hue = if hue < 0.0 { hue + 6.0 } else { hue };
+    let saturation = if max > 0.0 { delta / max } else { 0.0 };
+    [hue / 6.0, saturation, max]
+}
+
+// Weights of the four pixels around a point t of the way from the second
+// to the third
+fn catmull_rom(t: f32) -> [f32; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        -0.5 * t3 + t2 - 0.5 * t,
+        1.5 * t3 - 2.5 * t2 + 1.0,
+        -1.5 * t3 + 2.0 * t2 + 0.5 * t,
+        0.5 * t3 - 0.5 * t2,
+    ]
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 }
+    else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let c = value.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 { c * 12.92 }
+        else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A field of width by height with f in red, the other channels 0
+    fn field(width: u32, height: u32, f: impl Fn(f32, f32) -> f32) -> Field {
+        let texels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| [f(x as f32, y as f32), 0.0, 0.0, 1.0])
+            .collect();
+        Field { width, height, bits: 32, grey: false,
+            encoding: Encoding::Linear, texels }
+    }
+
+    fn red(t: [f32; 4]) -> f32 { t[0] }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} is not {}", a, b);
+    }
+
+    const SAMPLINGS: [Sampling; 3] =
+        [Sampling::Nearest, Sampling::Bilinear, Sampling::Bicubic];
+
+    #[test]
+    fn pixel_centres_are_exact() {
+        let field = field(4, 3, |x, y| x * x + 10.0 * y);
+        for sampling in SAMPLINGS {
+            for y in 0..3 {
+                for x in 0..4 {
+                    let (x, y) = (x as f32, y as f32);
+                    assert_near(field.sample((x, y), sampling, red),
+                        x * x + 10.0 * y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corners_are_exact() {
+        let field = field(4, 3, |x, y| x * x + 10.0 * y);
+        for sampling in SAMPLINGS {
+            assert_near(field.sample((0.0, 0.0), sampling, red), 0.0);
+            assert_near(field.sample((3.0, 0.0), sampling, red), 9.0);
+            assert_near(field.sample((0.0, 2.0), sampling, red), 20.0);
+            assert_near(field.sample((3.0, 2.0), sampling, red), 29.0);
+        }
+    }
+
+    #[test]
+    fn nearest_takes_the_closest_pixel() {
+        let field = field(4, 3, |x, y| x + 10.0 * y);
+        let nearest = |x, y| field.sample((x, y), Sampling::Nearest, red);
+        assert_near(nearest(1.4, 0.6), 11.0);
+        assert_near(nearest(1.6, 1.4), 12.0);
+        // halfway rounds up
+        assert_near(nearest(0.5, 1.5), 21.0);
+    }
+
+    #[test]
+    fn bilinear_is_the_mean_between_pixels() {
+        let field = field(2, 2, |x, y| [[1.0, 2.0], [4.0, 9.0]]
+            [y as usize][x as usize]);
+        assert_near(field.sample((0.5, 0.5), Sampling::Bilinear, red), 4.0);
+        assert_near(field.sample((0.5, 0.0), Sampling::Bilinear, red), 1.5);
+        assert_near(field.sample((0.0, 0.5), Sampling::Bilinear, red), 2.5);
+    }
+
+    #[test]
+    fn ramps_are_reproduced() {
+        let ramp = |x: f32, y: f32| 0.25 * x - 0.5 * y + 3.0;
+        let field = field(6, 5, ramp);
+        // away from the edges, where the bicubic taps are all inside
+        for (x, y) in [(1.0, 1.0), (1.25, 1.5), (2.5, 2.75), (3.9, 1.1)] {
+            assert_near(field.sample((x, y), Sampling::Bilinear, red),
+                ramp(x, y));
+            assert_near(field.sample((x, y), Sampling::Bicubic, red),
+                ramp(x, y));
+        }
+    }
+
+    #[test]
+    fn edges_are_clamped() {
+        let field = field(4, 3, |x, y| x + 10.0 * y);
+        for sampling in SAMPLINGS {
+            assert_near(field.sample((-2.0, 0.0), sampling, red), 0.0);
+            assert_near(field.sample((3.0, -1.5), sampling, red), 3.0);
+            assert_near(field.sample((5.0, 2.0), sampling, red), 23.0);
+            assert_near(field.sample((3.0, 4.0), sampling, red), 23.0);
+        }
+        // past the edge only the pixels inside count
+        assert_near(field.sample((3.5, 1.0), Sampling::Bilinear, red), 13.0);
+        assert_near(field.sample((1.0, 2.5), Sampling::Bilinear, red), 21.0);
+    }
+
+    #[test]
+    fn luma_of_the_primaries_is_their_weight() {
+        assert_near(luma([1.0, 0.0, 0.0]), 0.2126);
+        assert_near(luma([0.0, 1.0, 0.0]), 0.7152);
+        assert_near(luma([0.0, 0.0, 1.0]), 0.0722);
+        assert_near(luma([1.0, 1.0, 1.0]), 1.0);
+    }
+
+    #[test]
+    fn lightness_of_black_white_and_mid_grey() {
+        assert_near(lightness(0.0), 0.0);
+        assert_near(lightness(1.0), 1.0);
+        // 18% grey is close to L* 50
+        assert!((lightness(0.18) - 0.495).abs() < 1e-3);
+        // the two pieces meet
+        let epsilon = 216.0 / 24389.0;
+        assert_near(lightness(epsilon * 0.99999), lightness(epsilon));
+    }
+
+    #[test]
+    fn hsv_of_primaries_secondaries_and_greys() {
+        let cases = [
+            ([1.0, 0.0, 0.0], [0.0, 1.0, 1.0]),
+            ([1.0, 1.0, 0.0], [1.0 / 6.0, 1.0, 1.0]),
+            ([0.0, 1.0, 0.0], [2.0 / 6.0, 1.0, 1.0]),
+            ([0.0, 1.0, 1.0], [3.0 / 6.0, 1.0, 1.0]),
+            ([0.0, 0.0, 1.0], [4.0 / 6.0, 1.0, 1.0]),
+            ([1.0, 0.0, 1.0], [5.0 / 6.0, 1.0, 1.0]),
+            ([0.5, 0.0, 0.0], [0.0, 1.0, 0.5]),
+            ([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+            ([0.25, 0.25, 0.25], [0.0, 0.0, 0.25]),
+            ([1.0, 1.0, 1.0], [0.0, 0.0, 1.0]),
+        ];
+        for (rgb, expected) in cases {
+            let hsv = hsv(rgb);
+            for k in 0..3 {
+                assert_near(hsv[k], expected[k]);
+            }
+        }
+    }
+}
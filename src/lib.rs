@@ -5,7 +5,6 @@ use winit::{
     window::WindowBuilder,
 };
 use winit::window::Window;
-use image::io::Reader as ImageReader;
 use wgpu::util::DeviceExt;
 
 // use image::GenericImageView;
@@ -13,68 +12,136 @@ use wgpu::util::DeviceExt;
 // use anyhow::*;
 
 pub mod cli;
+mod colormap;
+pub mod error;
+mod export;
+mod filter;
+mod height;
+mod make_image;
 mod mesh;
 mod pipeline;
+mod shading;
 mod texture;
+mod tile;
 mod camera;
 
 struct State {
     args: cli::Args,
-    surface: wgpu::Surface,
+    // None when rendering offscreen
+    surface: Option<wgpu::Surface>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    image_text: texture::Texture, // image texture
+    images: Option<texture::Images>, // image and height textures
     mesh: mesh::Descriptor,
-    mesh_data: mesh::Data,
+    mesh_layout: wgpu::BindGroupLayout,
+    indices: mesh::Indices,
+    tiles: Option<tile::Tiles>, // in place of images and indices
+    layers: Vec<mesh::Data>, // one per rendered channel
+    shading: shading::Data,
+    pipelines: pipeline::Cache,
     depth: texture::Depth,
     // All this for the camera? Needs it's own struct?
     camera: camera::Camera,
     projection: camera::Projection,
     model_view: camera::ModelView,
     camera_controller: camera::CameraController,
-    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera_uniform: camera::CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    mouse_pressed: bool,
     // channel: i32,
 }
 
 impl State {
+    // The instance is a handle to our GPU
+    // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
+    fn instance() -> wgpu::Instance {
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        })
+    }
+
     // Creating some of the wgpu types requires async code
     async fn new(
         window: &Window,
         cli: &cli::Cli,
-    ) -> Self {
+    ) -> error::Result<Self> {
         let size = window.inner_size();
+        let instance = Self::instance();
 
-        // The instance is a handle to our GPU
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
-        
         // # Safety
         //
         // The surface needs to live as long as the window that created it.
         // State owns the window so this should be safe.
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = unsafe { instance.create_surface(&window) }
+            .map_err(error::Error::Surface)?;
+
+        Self::build(&instance, Some(surface), size, cli).await
+    }
+
+    // Renders into a texture rather than a window, no display needed.
+    async fn headless(cli: &cli::Cli) -> error::Result<Self> {
+        let size = winit::dpi::PhysicalSize::new(cli.width(), cli.height());
+        let instance = Self::instance();
 
-        let adapter = instance.request_adapter(
+        Self::build(&instance, None, size, cli).await
+    }
+
+    async fn adapter(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+        force_fallback_adapter: bool,
+    ) -> Option<wgpu::Adapter> {
+        instance.request_adapter(
             &wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                compatible_surface: surface,
+                force_fallback_adapter,
             },
-        ).await.unwrap();
+        ).await
+    }
+
+    async fn build(
+        instance: &wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        size: winit::dpi::PhysicalSize<u32>,
+        cli: &cli::Cli,
+    ) -> error::Result<Self> {
+        // Without a window there is nothing to be compatible with, so
+        // settle for a software adapter when no hardware one is found.
+        let adapter = match Self::adapter(
+            instance, surface.as_ref(), cli.fallback()).await {
+            Some(adapter) => Some(adapter),
+            None if surface.is_none() && !cli.fallback() =>
+                Self::adapter(instance, None, true).await,
+            None => None,
+        }.ok_or(error::Error::NoAdapter)?;
+        log::info!("{:?}", adapter.get_info());
+
+        // Read first, a bad file is the likelier mistake
+        let image = make_image::read_image(cli.image_name())?;
+
+        // Without line polygons the fragment shader draws the wire frame
+        let line = cli.polygon_mode() == wgpu::PolygonMode::Line
+            && adapter.features().contains(wgpu::Features::POLYGON_MODE_LINE);
+        if cli.polygon_mode() == wgpu::PolygonMode::Line && !line {
+            log::info!("No POLYGON_MODE_LINE, shading the wire frame");
+        }
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
                 // features: wgpu::Features::empty(),
-                features: wgpu::Features::POLYGON_MODE_LINE,
+                // Only the wire frame needs line polygons, asking for
+                // them otherwise rules out most software adapters.
+                features: if line {
+                    wgpu::Features::POLYGON_MODE_LINE
+                } else {
+                    wgpu::Features::empty()
+                // 16 bit images keep their precision where this is available
+                } | (adapter.features()
+                    & wgpu::Features::TEXTURE_FORMAT_16BIT_NORM),
                 // WebGL doesn't support all of wgpu's features, so if
                 // we're building for the web we'll have to disable some.
                 limits: if cfg!(target_arch = "wasm32") {
@@ -85,40 +152,90 @@ impl State {
                 label: None,
             },
             None, // Trace path
-        ).await.unwrap();
-
-        let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps.formats.iter()
-            .copied()
-            .filter(|f| f.describe().srgb)
-            .next()
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
+        ).await.map_err(error::Error::Device)?;
+
+        let config = match &surface {
+            Some(surface) => {
+                let surface_caps = surface.get_capabilities(&adapter);
+                // Shader code in this tutorial assumes an sRGB surface texture. Using a different
+                // one will result all the colors coming out darker. If you want to support non
+                // sRGB surfaces, you'll need to account for that when drawing to the frame.
+                let surface_format = surface_caps.formats.iter()
+                    .copied()
+                    .find(|f| f.describe().srgb)
+                    .unwrap_or(surface_caps.formats[0]);
+                let config = wgpu::SurfaceConfiguration {
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    format: surface_format,
+                    width: size.width,
+                    height: size.height,
+                    present_mode: surface_caps.present_modes[0],
+                    alpha_mode: surface_caps.alpha_modes[0],
+                    view_formats: vec![],
+                };
+                surface.configure(&device, &config);
+                config
+            }
+            // Describes the offscreen target, it is never used to
+            // configure a surface.
+            None => wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                width: size.width,
+                height: size.height,
+                present_mode: wgpu::PresentMode::Fifo,
+                alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+                view_formats: vec![],
+            },
         };
-        surface.configure(&device, &config);
 
-        let image_file = ImageReader::open(&cli.image_name()).expect(
-            "Error: Failed to open file");
-        let image = image_file.decode().expect(
-            "Error: Failed to read image");
-
-        let image_text = texture::Texture::from_image(
-            &device, &queue, &image, "image data").unwrap();
+        // Always bound, grey stands in when there is no colormap
+        let lut_data = colormap::lut(
+            cli.colormap().unwrap_or(cli::Colormap::Grey));
+        let max_size = device.limits().max_texture_dimension_2d;
+        let tiled = cli.lod()
+            || image.width() > max_size || image.height() > max_size;
+        let mut args = cli.args();
+        args.line_polygons = line;
+        let (images, mut tiles, dims) = if tiled {
+            let tiles = tile::Tiles::new(image, cli.height_encoding(),
+                cli.filters().to_vec(), lut_data, adapter.get_info().backend);
+            // Statistics of the coarsest level stand in for the image's
+            args.transfer.levels = mesh::levels(&tiles.overview(), &args);
+            // The coarsest tile, for the bind group layout, then kept by
+            // the tiles
+            let images = tiles.images(&device, &queue)?;
+            let dims = tiles.dimensions();
+            (images, Some(tiles), dims)
+        } else {
+            let color = texture::Texture::from_image(&device, &queue, &image,
+                cli.mipmaps(), wgpu::AddressMode::Repeat, "image data")?;
+            let field = filter::apply(height::Field::from_image(
+                &image, cli.height_encoding()), cli.filters(), 1.0);
+            args.transfer.levels = mesh::levels(&field, &args);
+            let height = texture::Texture::from_field(
+                &device, &queue, &field, cli.mipmaps(), "height data")?;
+            let lut = texture::Texture::from_lut(
+                &device, &queue, &lut_data, "colormap")?;
+            let images = texture::Images::new(
+                &device, color, height, lut, "images");
+            let dims = images.height.dimensions();
+            (images, None, dims)
+        };
 
 
-        let mesh = mesh::Descriptor::default(cli.xres(), cli.yres(),
-            cli.zoffset(), cli.zscale(), cli.channel());
-        let mesh_data = mesh::Data::new(mesh, &device);
+        let (xres, yres) = cli.grid_size(dims);
+        let mesh = mesh::Descriptor::default(dims, cli.pixel_aspect(),
+            xres, yres, cli.zoffset(), cli.zscale(), cli.channel())
+            .with_mip_levels(dims, images.height.texture.mip_level_count())
+            .with_format(images.height.texture.format());
+        let mesh_layout = mesh::Data::layout(&device);
+        let indices = mesh::Indices::new(
+            &mesh, &device, adapter.get_info().backend);
+        let layers = mesh::layers(&mesh, &args).into_iter()
+            .map(|desc| mesh::Data::new(desc, &device, &mesh_layout))
+            .collect();
 
         let depth = texture::Depth::create(&device, &config, "depth_texture");
 
@@ -128,7 +245,7 @@ impl State {
             cgmath::Deg(0.0), cgmath::Deg(0.0)); // model transformations
         let camera = camera::Camera::new(
             (0.0, 0.0, 3.0), cgmath::Deg(-90.0), cgmath::Deg(0.0));
-        let projection = camera::Projection::new(
+        let projection = camera::Projection::new(args.projection,
             config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
         let camera_controller = camera::CameraController::new(4.0, 0.4);
 
@@ -145,7 +262,8 @@ impl State {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // the fragment shaders light with view_position
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -165,32 +283,81 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let args = cli.args();
-
+        let shading_layout = shading::Data::layout(&device);
+        let shading = shading::Data::new(
+            shading::Uniform::new(&args), &device, &shading_layout);
+
+        let pipelines = pipeline::Cache::new(&device, &config, &[
+            &images.bind_group_layout,
+            &mesh_layout,
+            &camera_bind_group_layout,
+            &shading_layout,
+        ]);
+        let images = match &mut tiles {
+            Some(tiles) => {
+                tiles.load_root(&device, &mesh_layout, &mesh, &args, images);
+                None
+            }
+            None => Some(images),
+        };
 
-        Self {
+        Ok(Self {
             args,
             surface,
             device,
             queue,
             config,
             size,
-            image_text,
+            images,
             mesh,
-            mesh_data,
+            mesh_layout,
+            indices,
+            tiles,
+            layers,
+            shading,
+            pipelines,
             depth,
             camera,
             projection,
             model_view,
             camera_controller,
-            camera_bind_group_layout,
             camera_buffer,
             camera_bind_group,
             camera_uniform,
-            mouse_pressed: false,
             // channel: cli.channel(),
+        })
+
+    }
+
+    // Brings the layers up to date with new args. Uniforms are rewritten
+    // in place, they are only rebuilt when the number of layers changes.
+    // Pipelines are cached by key so they are built at most once.
+    fn set_args(&mut self, args: cli::Args) {
+        self.args = args;
+        mesh::update_layers(&mut self.layers,
+            mesh::layers(&self.mesh, &self.args),
+            &self.device, &self.queue, &self.mesh_layout);
+        self.shading.update(shading::Uniform::new(&self.args), &self.queue);
+        self.projection.mode = self.args.projection;
+        if let Some(tiles) = &mut self.tiles {
+            tiles.set_args(&self.device, &self.queue, &self.mesh_layout,
+                &self.args);
         }
+    }
 
+    // Keys that change what is displayed rather than where from.
+    fn process_keyboard(&mut self, key: VirtualKeyCode) -> bool {
+        let mut args = self.args;
+        match key {
+            // Walk the light around the surface
+            VirtualKeyCode::L => args.light.azimuth += 15.0,
+            VirtualKeyCode::K => args.light.azimuth -= 15.0,
+            VirtualKeyCode::P => args.projection = args.projection.toggle(),
+            VirtualKeyCode::G => args.display = args.display.next(),
+            _ => return false,
+        }
+        self.set_args(args);
+        true
     }
 
     // pub fn window(&self) -> &Window {
@@ -204,7 +371,9 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
             self.depth = texture::Depth::create(
                 &self.device, &self.config, "depth_texture");
         }
@@ -220,19 +389,18 @@ impl State {
                         ..
                     },
                 ..
-            } => self.camera_controller.process_keyboard(*key, *state),
+            } => self.camera_controller.process_keyboard(*key, *state)
+                || (*state == ElementState::Pressed
+                    && self.process_keyboard(*key)),
             WindowEvent::MouseWheel { delta, .. } => {
-                // self.camera_controller.process_scroll(delta);
-                // self.camera_controller.process_mouse(delta, delta);
+                self.camera_controller.process_scroll(delta);
                 true
             }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
-                true
+            WindowEvent::MouseInput { button, state, .. } =>
+                self.camera_controller.process_button(*button, *state),
+            WindowEvent::Focused(false) => {
+                self.camera_controller.release_buttons();
+                false
             }
             _ => false,
         }
@@ -245,6 +413,11 @@ impl State {
         self.camera_uniform.update_view_proj(&self.camera, &self.projection,
             &self.model_view);
         // println!("{:?}", self.camera_uniform);
+        if let Some(tiles) = &mut self.tiles {
+            tiles.update(&self.device, &self.queue, &self.mesh_layout,
+                &self.mesh, &self.args, self.camera_uniform.view_proj(),
+                (self.config.width, self.config.height));
+        }
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
@@ -257,13 +430,13 @@ impl State {
         view: &wgpu::TextureView,
         color: wgpu::Color,
     ) {
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             // There could be more than 1 render target.
             color_attachments: &[
                 // This is what @location(0) in the fragment shader targets
                 Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         // load: wgpu::LoadOp::Load,
@@ -279,22 +452,22 @@ impl State {
     fn render_pass(&mut self,
         encoder: &mut wgpu::CommandEncoder,
         view: &wgpu::TextureView,
-        zoffset: f32,
-        zscale: f32,
-        chn: cli::Channel,
+        layer: usize,
     ) {
-        let mesh = self.mesh.another(zoffset, zscale, chn);
-        let mesh_data = mesh::Data::new(mesh, &self.device);
-
-        let render_pipeline = pipeline::make(&self.device, &self.config,
-            &self.args,
-            chn,
-            &[
-                &self.image_text.bind_group_layout,
-                &mesh_data.layout,
-                &self.camera_bind_group_layout,
-            ]);
-
+        // Layers that only write their own colour channel each get a
+        // fresh depth buffer, layers writing every channel hide each other.
+        let index = layer;
+        let first = layer == 0;
+        let geometry = self.args.geometry();
+        let layer = &self.layers[layer];
+        let depth_load = if !first && self.args.color_writes(layer.channel())
+            == wgpu::ColorWrites::ALL {
+            wgpu::LoadOp::Load
+        } else {
+            wgpu::LoadOp::Clear(1.0)
+        };
+        let render_pipeline = self.pipelines.get(&self.device,
+            pipeline::Key::new(&self.args, layer.channel()));
 
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
@@ -302,7 +475,7 @@ impl State {
             color_attachments: &[
                 // This is what @location(0) in the fragment shader targets
                 Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         // must be zero to overwrite one image on another
@@ -324,67 +497,156 @@ impl State {
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: &self.depth.view,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    load: depth_load,
                     store: true,
                 }),
                 stencil_ops: None,
             }),
         });
 
-        render_pass.set_pipeline(&render_pipeline);
-        render_pass.set_bind_group(
-            1, &mesh_data.bind, &[]);
+        render_pass.set_pipeline(render_pipeline);
         render_pass.set_bind_group(
-            0, &self.image_text.bind_group, &[]); // NEW!
+            1, &layer.bind, &[]);
+        // tiles bind their own
+        if let Some(images) = &self.images {
+            render_pass.set_bind_group(0, &images.bind_group, &[]); // NEW!
+        }
         render_pass.set_bind_group(
             2, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(
+            3, &self.shading.bind, &[]);
 
-        render_pass.draw(
-            0..self.mesh_data.nverts(), 0..1); // 3.
+        match &self.tiles {
+            Some(tiles) => tiles.draw(&mut render_pass, index, geometry),
+            None => self.indices.draw(&mut render_pass, geometry), // 3.
+        }
 
     }
 
+    // Records the whole scene into view, shared by the window and the
+    // offscreen paths.
+    fn draw(&mut self, encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) {
+        self.clear(encoder, view, wgpu::Color::BLACK);
+        // self.clear(&mut encoder, &view,
+        //     wgpu::Color {r: 0.1, g: 0.2, b: 0.3, a: 1.0,}
+        // );
+        for layer in 0..self.layers.len() {
+            self.render_pass(encoder, view, layer);
+        }
+    }
+
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let output = self.surface.as_ref()
+            .expect("render needs a surface, use capture offscreen")
+            .get_current_texture()?;
         let view = output.texture.create_view(
             &wgpu::TextureViewDescriptor::default());
         let mut encoder = self.device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor {label: Some("Render Encoder"),});
-        self.clear(&mut encoder, &view, wgpu::Color::BLACK);
-        // self.clear(&mut encoder, &view,
-        //     wgpu::Color {r: 0.1, g: 0.2, b: 0.3, a: 1.0,}
-        // );
-        if !self.args.channel().is_rgb() {
-            self.render_pass(&mut encoder, &view,
-                0.0, self.args.zscale, self.args.channel());
-        } else {
-            use cli::Channel::*;
-            let colors = [Red, Green, Blue];
-            let mut zoffset = -1.0f32;
-            for chan in colors {
-                self.render_pass(&mut encoder, &view,
-                    zoffset, self.args.zscale, chan);
-                zoffset += self.args.zoffset;
-            }
-        }
+        self.draw(&mut encoder, &view);
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
     
         Ok(())
     }
+
+    // Renders one frame into a texture and reads the pixels back.
+    fn capture(&mut self) -> error::Result<image::RgbaImage> {
+        let size = wgpu::Extent3d {
+            width: self.config.width,
+            height: self.config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: self.config.usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Rows of a texture copy must be padded to a multiple of
+        // COPY_BYTES_PER_ROW_ALIGNMENT.
+        let unpadded_bytes_per_row = 4 * size.width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align)
+            * align;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {label: Some("Capture Encoder"),});
+        self.draw(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(
+                        padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(size.height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().map_err(error::Error::Readback)?;
+
+        let mut pixels = Vec::with_capacity(
+            (unpadded_bytes_per_row * size.height) as usize);
+        for row in slice.get_mapped_range()
+            .chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        buffer.unmap();
+
+        Ok(image::RgbaImage::from_raw(size.width, size.height, pixels)
+            .expect("pixels sized to the frame"))
+    }
     
 }
 
-pub async fn run(args: &cli::Cli) {
+pub async fn run(args: &cli::Cli) -> error::Result<()> {
     env_logger::init();
+    if let Some(cli::Command::Export { output, format }) = args.command() {
+        return export(args, output, *format);
+    }
+    if let Some(output) = args.output() {
+        return render_to_file(args, output).await;
+    }
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let window = WindowBuilder::new().build(&event_loop)
+        .map_err(error::Error::Window)?;
     // let mut state = State::new(window, cli, args).await;
-    let mut state = State::new(&window, args).await;
+    let mut state = State::new(&window, args).await?;
     let mut last_render_time = instant::Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
+        #[allow(clippy::collapsible_match)]
         match event {
             Event::WindowEvent {
                 ref event,
@@ -413,6 +675,14 @@ pub async fn run(args: &cli::Cli) {
                     }
                 }
             }
+            // Motion is taken from the device so drags keep going past the
+            // edge of the window
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } if state.camera_controller.is_dragging() => {
+                state.camera_controller.process_mouse(delta.0, delta.1)
+            }
             Event::RedrawRequested(window_id) if window_id == window.id() => {
                 let now = instant::Instant::now();
                 let dt = now - last_render_time;
@@ -438,6 +708,57 @@ pub async fn run(args: &cli::Cli) {
     });
 }
 
+// Renders a single frame without a window and saves it as an image file.
+pub async fn render_to_file(
+    args: &cli::Cli,
+    output: &std::path::Path,
+) -> error::Result<()> {
+    let mut state = State::headless(args).await?;
+    state.update(std::time::Duration::ZERO);
+    // All the tiles for the view, not just the first few
+    while state.tiles.as_ref().is_some_and(|tiles| tiles.pending()) {
+        state.update(std::time::Duration::ZERO);
+    }
+    let frame = state.capture()?;
+    frame.save(output)
+        .map_err(|err| error::Error::Save(output.to_path_buf(), err))
+}
+
+// Builds the surface on the CPU and writes it to a mesh file.
+pub fn export(
+    args: &cli::Cli,
+    output: &std::path::Path,
+    format: Option<cli::ExportFormat>,
+) -> error::Result<()> {
+    let format = format
+        .or_else(|| cli::ExportFormat::from_path(output))
+        .ok_or_else(|| error::Error::MeshFormat(output.to_path_buf()))?;
+    let image = make_image::read_image(args.image_name())?;
+    let field = filter::apply(
+        height::Field::from_image(&image, args.height_encoding()),
+        args.filters(), 1.0);
+
+    let dims = field.dimensions();
+    let (xres, yres) = args.grid_size(dims);
+    let levels = if args.mipmaps() { field.mip_levels() } else { 1 };
+    let mesh = mesh::Descriptor::default(dims, args.pixel_aspect(),
+        xres, yres, args.zoffset(), args.zscale(), args.channel())
+        .with_mip_levels(dims, levels);
+    let mut layer_args = args.args();
+    layer_args.transfer.levels = mesh::levels(&field, &layer_args);
+    // every layer samples the same level
+    let heights = field.level(mesh.level());
+    let layers: Vec<export::Layer> = mesh::layers(&mesh, &layer_args).iter()
+        .map(|desc| export::Layer {
+            name: format!("{:?}", desc.channel()).to_lowercase(),
+            channel: desc.channel(),
+            grid: desc.grid(dims, &heights),
+        })
+        .collect();
+    export::write(output, format, &layers, args.image_name(), &image)
+        .map_err(|err| error::Error::Export(output.to_path_buf(), err))
+}
+
         // let mesh_uniform = uniform_buffer::UniformBinding::new(
         //     mesh_desc.buffer(&device), &device
         // );
@@ -595,5 +916,3 @@ pub async fn run(args: &cli::Cli) {
 
     
     
-
-
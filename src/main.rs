@@ -9,5 +9,9 @@ fn main() {
     let cli = Cli::new();
     println!("{:?}", cli);
 
-    pollster::block_on(run(&cli));
+    // Reported plainly, the tool is scripted and a backtrace is no help
+    if let Err(err) = pollster::block_on(run(&cli)) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
 }
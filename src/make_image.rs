@@ -1,7 +1,31 @@
-use image_view::cli;
+// Reads the image the surface is built from.
+use std::path::Path;
+
 use image::io::Reader as ImageReader;
-use anyhow::*;
+use image::ImageError;
+
+use crate::error::{Error, Result};
 
-pub fn read_image(image_name: PathBuf) -> image::DynamicImage {
-    let image = ImageReader::open(image_name)?.decode()?;
+// The format is taken from the contents, the extension only when they
+// don't say. The decoder's limits are lifted, the viewer tiles images
+// past the device limits and the export has no texture to fit.
+pub fn read_image(image_name: &Path) -> Result<image::DynamicImage> {
+    let open = |err| Error::Open(image_name.to_path_buf(), err);
+    let mut reader = ImageReader::open(image_name).map_err(open)?
+        .with_guessed_format().map_err(open)?;
+    reader.no_limits();
+    if reader.format().is_none() {
+        return Err(Error::Unsupported(image_name.to_path_buf()));
+    }
+    reader.decode().map_err(|err| match err {
+        ImageError::Unsupported(_) =>
+            Error::Unsupported(image_name.to_path_buf()),
+        ImageError::Limits(_) => Error::TooLarge {
+            name: image_name.display().to_string(),
+            dims: image::image_dimensions(image_name).unwrap_or_default(),
+            max: None,
+        },
+        ImageError::IoError(err) => open(err),
+        err => Error::Decode(image_name.to_path_buf(), err),
+    })
 }
@@ -1,5 +1,29 @@
+use std::ops::Range;
 use wgpu::util::DeviceExt;
-use crate::cli;
+use crate::{cli, height};
+
+// Upper bound on the size of the index buffer, larger grids draw it once
+// per band of rows.
+const INDEX_BAND_BYTES: u32 = 1 << 24;
+// Grids are drawn as a triangle strip per row of quads, ended by the
+// restart index, the largest Uint32.
+pub const INDEX_FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
+pub const TOPOLOGY: wgpu::PrimitiveTopology =
+    wgpu::PrimitiveTopology::TriangleStrip;
+const RESTART: u32 = u32::MAX;
+// Vertexes of the box on each quad in bars mode, two triangles for the
+// top and for each side
+pub const BAR_VERTICES: u32 = 30;
+// Of the log transfer, how far the faintest values are lifted
+const LOG_STRETCH: f32 = 1000.0;
+
+// How the vertex shader makes primitives of the vertex index
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Geometry {
+    Surface,     // grid vertexes joined into strips by the index buffer
+    Points(u32), // vertexes for each grid vertex, 1 for the device's points
+    Bars,        // BAR_VERTICES for each quad
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -13,9 +37,24 @@ pub struct Descriptor {
     yscale: f32,        // y scale factor
     zscale: f32,        // z scale factor
     channel: i32,       // red, green or blue color channel
+    skirt: f32,         // drop of a ring of vertexes around the edge, 0 for none
+    sampling: u32,      // cli::Sampling of the heights
+    level: u32,         // mip level the heights are sampled from
+    // The transfer of channel values to heights, see transfer
+    remap: u32,         // 0 when the heights are the channel values
+    curve: u32,         // cli::Curve
+    power: f32,         // exponent of Curve::Power
+    invert: u32,        // 1 for high values lowest
+    lo: f32,            // channel value at the bottom
+    hi: f32,            // and at the top
+    grey: u32,          // 1 when the height texture holds only red
+    _padding: u32,      // mix starts on 16 bytes
+    mix: [f32; 4],      // weights of rgba in Channel::Mix
+    cdf: [[f32; 4]; height::CDF_BINS / 4], // see height::Levels
 }
 
 impl Descriptor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         rowsize: u32,   // number of vertexes in a row
         nrows: u32,     // number of rows
@@ -37,59 +76,435 @@ impl Descriptor {
             yscale,
             zscale,
             channel,
+            skirt: 0.0,
+            sampling: cli::Sampling::default() as u32,
+            level: 0,
+            remap: 0,
+            curve: 0,
+            power: 1.0,
+            invert: 0,
+            lo: 0.0,
+            hi: 1.0,
+            grey: 0,
+            _padding: 0,
+            mix: [0.0; 4],
+            cdf: [[0.0; 4]; height::CDF_BINS / 4],
         }
     }
-    // Sets up so that the scale goes from -1 to +1 for both
-    // x vertexes and y vertexes
+    // Sets up so that the longer side of the image goes from -1 to +1 and
+    // the shorter side keeps the aspect ratio of the image
     pub fn default(
-        rowsize: u32,   // number of vertexes in a row
-        nrows: u32,     // number of rows of vertexes
+        dims: (u32, u32),  // image width and height in pixels
+        pixel_aspect: f32, // width of a pixel over its height
+        rowsize: u32,      // number of vertexes in a row
+        nrows: u32,        // number of rows of vertexes
         zoffset: f32,
         zscale: f32,
         chan: cli::Channel,
     ) -> Self {
         let quads_in_row = rowsize - 1;
         let rows_of_quads = nrows - 1;
-        let xscale = 2.0 / quads_in_row as f32;
-        let yscale = 2.0 / rows_of_quads as f32;
-        Self {
-            quads_in_row,
-            rows_of_quads,
-            xoffset: -1.0,
-            yoffset: -1.0,
-            zoffset,
-            xscale,
-            yscale,
-            zscale,
-            channel: chan.value(),
+        let aspect = dims.0 as f32 * pixel_aspect / dims.1 as f32;
+        let (xsize, ysize) = if aspect >= 1.0 { (1.0, 1.0 / aspect) }
+            else { (aspect, 1.0) };
+        let xscale = 2.0 * xsize / quads_in_row as f32;
+        let yscale = 2.0 * ysize / rows_of_quads as f32;
+        Self::new(rowsize, nrows, -xsize, -ysize, zoffset,
+            xscale, yscale, zscale, chan.value())
+    }
+    // For a height texture of a single channel, a grey image, which the
+    // shader spreads over rgb
+    pub fn with_format(&self, format: wgpu::TextureFormat) -> Self {
+        Descriptor {
+            grey: (format.describe().components == 1) as u32,
+            ..*self
         }
     }
-    pub fn another(&self, zoffset: f32, zscale: f32, chan: cli::Channel,
+    // Samples the heights from the mip level, of levels, with about a
+    // pixel per quad so each vertex stands for the area around it
+    pub fn with_mip_levels(&self, dims: (u32, u32), levels: u32) -> Self {
+        let step = ((dims.0 - 1) as f32 / self.quads_in_row as f32)
+            .min((dims.1 - 1) as f32 / self.rows_of_quads as f32);
+        let level = if step >= 2.0 { step.log2().floor() as u32 } else { 0 };
+        Descriptor {
+            level: level.min(levels.saturating_sub(1)),
+            ..*self
+        }
+    }
+    // The part of the image from first, in pixels of a pyramid level that
+    // has a pixel every step pixels of the image, with a vertex for every
+    // pixel of the level. self is the whole image of dims, the skirt hides
+    // the cracks between tiles of different levels.
+    pub fn tile(
+        &self,
+        dims: (u32, u32),
+        step: u32,
+        first: (u32, u32), // column and row from the top left
+        quads: (u32, u32),
+        skirt: f32,
     ) -> Descriptor {
+        // size of an image pixel
+        let xpixel = self.xscale * self.quads_in_row as f32
+            / (dims.0 - 1) as f32;
+        let ypixel = self.yscale * self.rows_of_quads as f32
+            / (dims.1 - 1) as f32;
+        // image rows run down, grid rows up
+        let bottom = (first.1 + quads.1) * step;
+        Descriptor {
+            quads_in_row: quads.0,
+            rows_of_quads: quads.1,
+            xoffset: self.xoffset + (first.0 * step) as f32 * xpixel,
+            yoffset: self.yoffset
+                + ((dims.1 - 1) as f32 - bottom as f32) * ypixel,
+            xscale: xpixel * step as f32,
+            yscale: ypixel * step as f32,
+            skirt,
+            level: 0, // tiles are already a pyramid level
+            ..*self
+        }
+    }
+    pub fn another(&self, zoffset: f32, chan: cli::Channel, args: &cli::Args,
+    ) -> Descriptor {
+        let transfer = &args.transfer;
+        let levels = transfer.levels(chan);
+        let mut cdf = [[0.0; 4]; height::CDF_BINS / 4];
+        for (i, c) in levels.cdf.iter().enumerate() {
+            cdf[i / 4][i % 4] = *c;
+        }
         Descriptor {
             zoffset,
-            zscale,
+            zscale: args.zscale,
             channel: chan.value(),
+            sampling: args.sampling as u32,
+            remap: !transfer.is_identity() as u32,
+            curve: transfer.curve as u32,
+            power: transfer.power,
+            invert: transfer.invert as u32,
+            lo: levels.lo,
+            hi: levels.hi,
+            mix: args.mix,
+            cdf,
             ..*self
         }
     }
-    pub fn nverts(self: &Self) -> u32 {
-        self.quads_in_row * self.rows_of_quads * 6
+    // Vertexes in the grid, each shaded once
+    pub fn nverts(&self) -> u32 {
+        (self.quads_in_row + 1) * (self.rows_of_quads + 1)
+    }
+    // Vertexes of the bars, which have no skirt
+    pub fn bar_verts(&self) -> u32 {
+        self.quads_in_row * self.rows_of_quads * BAR_VERTICES
+    }
+    // Vertexes in a row and number of rows drawn, including the skirt
+    pub fn grid_dims(&self) -> (u32, u32) {
+        let ring = if self.skirt > 0.0 { 2 } else { 0 };
+        (self.quads_in_row + 1 + ring, self.rows_of_quads + 1 + ring)
+    }
+    pub fn quads(&self) -> (u32, u32) { (self.quads_in_row, self.rows_of_quads) }
+    pub fn zscale(&self) -> f32 { self.zscale }
+    pub fn skirt(&self) -> f32 { self.skirt }
+    pub fn level(&self) -> u32 { self.level }
+    // Corners of the grid in x and y
+    pub fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        ((self.xoffset, self.yoffset), (
+            self.xoffset + self.quads_in_row as f32 * self.xscale,
+            self.yoffset + self.rows_of_quads as f32 * self.yscale,
+        ))
+    }
+    // Lowest and highest value of the channel, and so of the heights
+    fn range(&self) -> [f32; 2] { self.channel().range(self.mix) }
+    // Lowest and highest z the surface can reach
+    pub fn z_range(&self) -> (f32, f32) {
+        let [bottom, top] = self.range();
+        let (a, b) = (bottom * self.zscale + self.zoffset,
+            top * self.zscale + self.zoffset);
+        (a.min(b) - self.skirt, a.max(b))
+    }
+    pub fn channel(&self) -> cli::Channel {
+        cli::Channel::from_value(self.channel)
+    }
+    pub fn sampling(&self) -> cli::Sampling {
+        cli::Sampling::from_value(self.sampling)
+    }
+
+    // Position in pixels of the image of dims under a grid point, the
+    // corner vertexes on the corner pixels. Same as pixel in the shader.
+    pub fn pixel(&self, dims: (u32, u32), x: f32, y: f32) -> (f32, f32) {
+        (x * (dims.0 - 1) as f32 / self.quads_in_row as f32,
+        (self.rows_of_quads as f32 - y) * (dims.1 - 1) as f32
+            / self.rows_of_quads as f32)
+    }
+
+    // The CPU side of vs_main, the displaced surface as a vertex per
+    // grid point and two triangles per quad. heights is the mip level
+    // the heights come from of a field of dims, as height_tex has.
+    pub fn grid(&self, dims: (u32, u32), heights: &height::Field) -> Grid {
+        let (width, height) = dims;
+        let scale = (1 << self.level) as f32;
+        let rowsize = self.quads_in_row + 1;
+        let nrows = self.rows_of_quads + 1;
+        let mut grid = Grid {
+            positions: Vec::with_capacity(self.nverts() as usize),
+            uvs: Vec::with_capacity(self.nverts() as usize),
+            triangles: Vec::with_capacity(
+                (self.quads_in_row * self.rows_of_quads * 2) as usize),
+        };
+        for row in 0..nrows {
+            for col in 0..rowsize {
+                let x = col as f32;
+                let y = row as f32;
+                let pixel = self.pixel((width, height), x, y);
+                // the same point in the pixels of the mip level
+                let at = ((pixel.0 + 0.5) / scale - 0.5,
+                    (pixel.1 + 0.5) / scale - 0.5);
+                let value = self.transfer(self.sample(heights, at));
+                grid.positions.push([
+                    x * self.xscale + self.xoffset,
+                    y * self.yscale + self.yoffset,
+                    value * self.zscale + self.zoffset,
+                ]);
+                // centres of the pixels, as image_tex
+                grid.uvs.push([
+                    (pixel.0 + 0.5) / width as f32,
+                    (pixel.1 + 0.5) / height as f32,
+                ]);
+            }
+        }
+        for row in 0..self.rows_of_quads {
+            for col in 0..self.quads_in_row {
+                grid.triangles.extend(quad(row, col, rowsize));
+            }
+        }
+        grid
+    }
+
+    // Channel value at a point in pixels of heights, as sample_value in
+    // the shader. Hue is interpolated round its circle, as a direction.
+    fn sample(&self, heights: &height::Field, at: (f32, f32)) -> f32 {
+        if self.channel() != cli::Channel::Hue
+            || self.sampling() == cli::Sampling::Nearest {
+            return heights.sample(at, self.sampling(), |rgba| self.value(rgba));
+        }
+        use std::f32::consts::TAU;
+        let along = |f: fn(f32) -> f32| heights.sample(at, self.sampling(),
+            |rgba| f(TAU * self.value(rgba)));
+        let angle = along(f32::sin).atan2(along(f32::cos));
+        (angle / TAU + 1.0).fract()
+    }
+
+    // The channel switch of channel_value in the shader.
+    pub fn value(&self, rgba: [f32; 4]) -> f32 {
+        channel_value(self.channel(), self.mix, rgba)
+    }
+
+    // Height value of a channel value, as transfer in the shader. From lo
+    // to hi is clamped and stretched over the values the channel can take
+    // then bent by the curve.
+    pub fn transfer(&self, value: f32) -> f32 {
+        if self.remap == 0 {
+            return value;
+        }
+        let t = ((value - self.lo) / (self.hi - self.lo)).clamp(0.0, 1.0);
+        let t = match cli::Curve::from_value(self.curve) {
+            cli::Curve::Linear => t,
+            cli::Curve::Log =>
+                (1.0 + LOG_STRETCH * t).ln() / (1.0 + LOG_STRETCH).ln(),
+            cli::Curve::Power => t.powf(self.power),
+            cli::Curve::Equalize => {
+                let x = t * (height::CDF_BINS - 1) as f32;
+                let i = (x as usize).min(height::CDF_BINS - 2);
+                let cdf = |i: usize| self.cdf[i / 4][i % 4];
+                cdf(i) + (cdf(i + 1) - cdf(i)) * (x - i as f32)
+            }
+        };
+        let t = if self.invert != 0 { 1.0 - t } else { t };
+        let [bottom, top] = self.range();
+        bottom + t * (top - bottom)
+    }
+}
+
+fn channel_value(chan: cli::Channel, mix: [f32; 4], rgba: [f32; 4]) -> f32 {
+    let rgb = [rgba[0], rgba[1], rgba[2]];
+    match chan {
+        cli::Channel::Red => rgba[0],
+        cli::Channel::Green => rgba[1],
+        cli::Channel::Blue => rgba[2],
+        cli::Channel::Luma => height::luma(rgb),
+        cli::Channel::Lightness => height::lightness(height::luma(rgb)),
+        cli::Channel::Hue => height::hsv(rgb)[0],
+        cli::Channel::Saturation => height::hsv(rgb)[1],
+        cli::Channel::Value => height::hsv(rgb)[2],
+        cli::Channel::Alpha => rgba[3],
+        cli::Channel::Mix => (0..4).map(|k| mix[k] * rgba[k]).sum(),
+        _ => (rgba[0] * rgba[0] + rgba[1] * rgba[1]
+            + rgba[2] * rgba[2]).sqrt(),
+    }
+}
+
+// The levels of the transfer for each channel, measured over the heights
+// when they depend on them.
+pub fn levels(
+    field: &height::Field,
+    args: &cli::Args,
+) -> [height::Levels; cli::CHANNELS] {
+    let (transfer, mix) = (&args.transfer, args.mix);
+    if !transfer.measures() {
+        return transfer.levels;
+    }
+    std::array::from_fn(|i| {
+        let chan = cli::Channel::from_value(i as i32);
+        let levels = transfer.levels(chan);
+        // drawn as its red, green and blue layers
+        if chan.is_rgb() {
+            return *levels;
+        }
+        // normalize measures the range, equalize only the histogram
+        let range = (!transfer.normalize || transfer.range.is_some())
+            .then_some([levels.lo, levels.hi]);
+        field.levels(|rgba| channel_value(chan, mix, rgba), range)
+    })
+}
+
+// The mesh of each layer drawn for args, Rgb draws a red, green and
+// blue layer stacked zoffset apart.
+pub fn layers(mesh: &Descriptor, args: &cli::Args) -> Vec<Descriptor> {
+    if !args.channel().is_rgb() {
+        vec![mesh.another(0.0, args.channel(), args)]
+    } else {
+        use cli::Channel::*;
+        let colors = [Red, Green, Blue];
+        let mut zoffset = -1.0f32;
+        colors.into_iter().map(|chan| {
+            let layer = mesh.another(zoffset, chan, args);
+            zoffset += args.zoffset;
+            layer
+        }).collect()
+    }
+}
+
+// The two triangles of a quad, as indexes of grid vertexes. Same winding
+// as the offsets in vs_main.
+fn quad(row: u32, col: u32, rowsize: u32) -> [[u32; 3]; 2] {
+    let v00 = row * rowsize + col;
+    let v10 = v00 + 1;
+    let v01 = v00 + rowsize;
+    let v11 = v01 + 1;
+    [[v00, v11, v01], [v00, v10, v11]]
+}
+
+// A row of quads as a strip, alternating the upper and lower vertex of
+// each column. This gives the diagonals and winding of quad.
+fn strip(row: u32, rowsize: u32) -> impl Iterator<Item = u32> {
+    (0..rowsize)
+        .flat_map(move |col| [(row + 1) * rowsize + col, row * rowsize + col])
+        .chain(std::iter::once(RESTART))
+}
+
+// Index buffer shared by every layer. vs_main pulls the grid vertex
+// named by the index, so a vertex is computed at most once for each of the
+// two rows of quads it is in. The buffer only holds a band of rows, draws
+// of later bands offset it with a base vertex.
+pub struct Indices {
+    buffer: wgpu::Buffer,
+    per_row: u32,       // indexes in a row of quads
+    rows_per_band: u32,
+    rows_of_quads: u32,
+    rowsize: u32,       // vertexes in a row
+    restart: bool,      // whether the backend restarts strips
+    bar_verts: u32,
+}
+
+impl Indices {
+    // wgpu's GL backend never enables primitive restart, there each row
+    // is drawn on its own, stopping short of the restart index.
+    pub fn new(
+        desc: &Descriptor,
+        device: &wgpu::Device,
+        backend: wgpu::Backend,
+    ) -> Self {
+        let (rowsize, nrows) = desc.grid_dims();
+        let rows_of_quads = nrows - 1;
+        let per_row = rowsize * 2 + 1;
+        let rows_per_band = (INDEX_BAND_BYTES / (per_row * 4))
+            .clamp(1, rows_of_quads);
+        let indices: Vec<u32> = (0..rows_per_band)
+            .flat_map(|row| strip(row, rowsize))
+            .collect();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("mesh::Indices buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Self {
+            buffer,
+            per_row,
+            rows_per_band,
+            rows_of_quads,
+            rowsize,
+            restart: backend != wgpu::Backend::Gl,
+            bar_verts: desc.bar_verts(),
+        }
+    }
+
+    // Draws the grid with whatever is bound. Points and bars need no
+    // index buffer, only their number of vertexes.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        geometry: Geometry,
+    ) {
+        match geometry {
+            Geometry::Surface => (),
+            Geometry::Points(per_point) => {
+                let nverts = self.rowsize * (self.rows_of_quads + 1);
+                return render_pass.draw(0..nverts * per_point, 0..1);
+            }
+            Geometry::Bars => return render_pass.draw(0..self.bar_verts, 0..1),
+        }
+        render_pass.set_index_buffer(self.buffer.slice(..), INDEX_FORMAT);
+        for (indices, base_vertex) in self.draws() {
+            render_pass.draw_indexed(indices, base_vertex, 0..1);
+        }
+    }
+
+    // Index range and base vertex of each draw covering the grid
+    fn draws(&self) -> Box<dyn Iterator<Item = (Range<u32>, i32)> + '_> {
+        let bands = (0..self.rows_of_quads)
+            .step_by(self.rows_per_band as usize)
+            .map(|start| (start, self.rows_per_band
+                .min(self.rows_of_quads - start)));
+        let base = move |start: u32| (start * self.rowsize) as i32;
+        if self.restart {
+            Box::new(bands.map(move |(start, rows)|
+                (0..rows * self.per_row, base(start))))
+        } else {
+            Box::new(bands.flat_map(move |(start, rows)| (0..rows)
+                .map(move |row| {
+                    let first = row * self.per_row;
+                    (first..first + self.per_row - 1, base(start))
+                })))
+        }
     }
 }
 
+// Indexed triangle mesh built on the CPU, for export.
+pub struct Grid {
+    pub positions: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>, // texture coordinates, v down as in wgpu
+    pub triangles: Vec<[u32; 3]>,
+}
+
+// Mesh uniform for one rendered layer. The buffer lives as long as the
+// layer so it is only written, never recreated, when the mesh changes.
 pub struct Data {
     pub desc: Descriptor,
-    pub layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
     pub bind: wgpu::BindGroup,
 }
 
 impl Data {
-    pub fn new(
-        desc: Descriptor,
-        device: &wgpu::Device,
-    ) -> Self {
-        let layout =
+    // Shared by every layer, and by the pipelines that draw them.
+    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
@@ -102,8 +517,14 @@ impl Data {
                 count: None,
             }],
             label: Some("mesh::Data bind_group_layout"),
-        });
+        })
+    }
 
+    pub fn new(
+        desc: Descriptor,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("mesh::Data buffer"),
             contents: bytemuck::cast_slice(&[desc]),
@@ -111,7 +532,7 @@ impl Data {
         });
 
         let bind = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &layout,
+            layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: buffer.as_entire_binding(),
@@ -120,12 +541,38 @@ impl Data {
         });
         Self {
             desc,
-            layout,
+            buffer,
             bind,
         }
     }
-    pub fn nverts(&self) -> u32 { self.desc.nverts() }
-    // pub fn channel(&self) -> i32 { self.desc.channel }
+    // Writes a changed descriptor into the existing buffer.
+    pub fn update(&mut self, desc: Descriptor, queue: &wgpu::Queue) {
+        if bytemuck::bytes_of(&desc) != bytemuck::bytes_of(&self.desc) {
+            self.desc = desc;
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[desc]));
+        }
+    }
+    pub fn channel(&self) -> cli::Channel { self.desc.channel() }
+}
+
+// Brings layers up to date with meshes, writing the uniforms in place
+// unless the number of layers changed.
+pub fn update_layers(
+    layers: &mut Vec<Data>,
+    meshes: Vec<Descriptor>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) {
+    if meshes.len() == layers.len() {
+        for (layer, desc) in layers.iter_mut().zip(meshes) {
+            layer.update(desc, queue);
+        }
+    } else {
+        *layers = meshes.into_iter()
+            .map(|desc| Data::new(desc, device, layout))
+            .collect();
+    }
 }
 
 // pub enum Vary {
@@ -231,3 +678,22 @@ impl Data {
     //         }
     //     )
     // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_puts_the_corner_vertexes_on_the_corner_pixels() {
+        let dims = (640, 480);
+        let desc = Descriptor::default(dims, 1.0, 33, 17, 0.0, 1.0,
+            cli::Channel::Red);
+        // grid rows run up, image rows down
+        assert_eq!(desc.pixel(dims, 0.0, 0.0), (0.0, 479.0));
+        assert_eq!(desc.pixel(dims, 32.0, 0.0), (639.0, 479.0));
+        assert_eq!(desc.pixel(dims, 0.0, 16.0), (0.0, 0.0));
+        assert_eq!(desc.pixel(dims, 32.0, 16.0), (639.0, 0.0));
+        // and the middle vertex on the middle of the image
+        assert_eq!(desc.pixel(dims, 16.0, 8.0), (319.5, 239.5));
+    }
+}
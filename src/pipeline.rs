@@ -1,46 +1,96 @@
+use std::collections::HashMap;
+
+#[allow(clippy::single_component_path_imports)]
 use wgpu;
 
-use crate::{texture, cli};
+use crate::{texture, cli, mesh};
+
+// Everything that makes one render pipeline differ from another.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Key {
+    pub polygon_mode: wgpu::PolygonMode,
+    pub frag_entry: &'static str,
+    pub color_writes: wgpu::ColorWrites,
+    pub topology: wgpu::PrimitiveTopology,
+}
+
+impl Key {
+    pub fn new(args: &cli::Args, chan: cli::Channel) -> Self {
+        Self {
+            polygon_mode: args.polygon_mode(),
+            frag_entry: args.frag_entry(),
+            color_writes: args.color_writes(chan),
+            topology: args.topology(),
+        }
+    }
+}
+
+// Compiles the shader once and builds each pipeline the first time it
+// is asked for.
+pub struct Cache {
+    shader: wgpu::ShaderModule,
+    layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    pipelines: HashMap<Key, wgpu::RenderPipeline>,
+}
+
+impl Cache {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+        let layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+        Self {
+            shader,
+            layout,
+            format: config.format,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, key: Key)
+        -> &wgpu::RenderPipeline
+    {
+        let Self { shader, layout, format, pipelines } = self;
+        pipelines.entry(key).or_insert_with(
+            || make(device, shader, layout, *format, key))
+    }
+}
 
 pub fn make(
     device: &wgpu::Device,
-    config: &wgpu::SurfaceConfiguration,
-    args: &cli::Args,
-    chan: cli::Channel,
+    shader: &wgpu::ShaderModule,
+    render_pipeline_layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    key: Key,
     // image_text: &texture::Texture,
     // mesh_uniform: &uniform_buffer::UniformBinding,
     // camera_uniform: &camera::CameraUniform,
-    bind_group_layouts: &[&wgpu::BindGroupLayout]
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: Some("Shader"),
-        source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-    });
-    let render_pipeline_layout =
-        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: bind_group_layouts,
-            // &[
-            //     &image_text.bind_group_layout,
-            //     &mesh_uniform.bind_group_layout,
-            //     &camera_uniform.bind
-            // ],
-            // bind_group_layouts: bind_group_layouts, // NEW!
-            push_constant_ranges: &[],
-        });
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    log::info!("Building pipeline {:?}", key);
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
-        layout: Some(&render_pipeline_layout),
+        layout: Some(render_pipeline_layout),
         vertex: wgpu::VertexState {
-            module: &shader,
+            module: shader,
             entry_point: "vs_main", // 1.
             buffers: &[], // 2.
         },
         fragment: Some(wgpu::FragmentState { // 3.
-            module: &shader,
-            entry_point: &args.frag_entry(),
+            module: shader,
+            entry_point: key.frag_entry,
             targets: &[Some(wgpu::ColorTargetState { // 4.
-                format: config.format,
+                format,
                 blend: Some(wgpu::BlendState::REPLACE),
                 // blend: Some(wgpu::BlendState {
                 //     color: wgpu::BlendComponent {
@@ -54,19 +104,21 @@ pub fn make(
                 //         operation: wgpu::BlendOperation::Add,
                 //     }
                 // }),
-                write_mask: cli::Channel::color_writes(&chan),
+                write_mask: key.color_writes,
                 // write_mask: wgpu::ColorWrites::ALL,
                 // write_mask: args.color_writes(),
             })],
         }),
         primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-            strip_index_format: None,
+            topology: key.topology, // 1.
+            // points are drawn without the index buffer
+            strip_index_format: (key.topology == mesh::TOPOLOGY)
+                .then_some(mesh::INDEX_FORMAT),
             front_face: wgpu::FrontFace::Ccw, // 2.
             // cull_mode: Some(wgpu::Face::Back),
             cull_mode: None,
             // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-            polygon_mode: args.polygon_mode(),
+            polygon_mode: key.polygon_mode,
             // polygon_mode: wgpu::PolygonMode::Fill,
             // polygon_mode: wgpu::PolygonMode::Line,
             // Requires Features::DEPTH_CLIP_CONTROL
@@ -88,6 +140,5 @@ pub fn make(
             alpha_to_coverage_enabled: false, // 4.
         },
         multiview: None, // 5.
-    });
-    render_pipeline
+    })
 }
@@ -3,6 +3,9 @@
 struct CameraUniform {
     view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
+    model: mat4x4<f32>,  // mesh to world
+    normal: mat4x4<f32>, // inverse transpose of model
+    viewport: vec4<f32>, // width and height in pixels
 };
 @group(2) @binding(0)
 var<uniform> camera: CameraUniform;
@@ -17,21 +20,57 @@ struct MeshDescriptor {
     yscale: f32,        // y scale factor
     zscale: f32,        // z scale factor
     channel: i32,       // red, green or blue color channel
+    skirt: f32,         // drop of a ring of vertexes around the edge
+    sampling: u32,      // nearest, bilinear or bicubic heights
+    level: u32,         // mip level of height_tex the heights come from
+    remap: u32,         // 0 when the heights are the channel values
+    curve: u32,         // linear, log, power or equalize
+    power: f32,         // exponent of the power curve
+    invert: u32,        // 1 for high values lowest
+    lo: f32,            // channel value at the bottom
+    hi: f32,            // and at the top
+    grey: u32,          // 1 when height_tex holds only red
+    mix: vec4<f32>,     // weights of rgba in the mix channel
+    cdf: array<vec4<f32>, 16>, // share of pixels at or below 64 values
 };
 
 @group(1) @binding(0)
 var<uniform> mesh_desc: MeshDescriptor;
 
+struct Shading {
+    light_dir: vec4<f32>, // towards the light, world space
+    light: vec4<f32>,     // ambient, diffuse, specular, shininess
+    lighting: u32,        // none, lambert or blinn-phong
+    nlevels: u32,         // number of contour levels used
+    interval: f32,        // contour interval, 0 for none
+    width: f32,           // contour width in pixels
+    contour_color: vec4<f32>,
+    levels: array<vec4<f32>, 4>, // four contour levels to an element
+    wire_color: vec4<f32>, // overlay grid colour, opacity in a, 0 for none
+    point_size: f32,       // pixels
+    points: u32,           // vertexes per point, 0 for the surface
+    bars: u32,             // vertexes per bar, 0 for the surface
+};
+
+@group(3) @binding(0)
+var<uniform> shading: Shading;
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) wire_tex: vec2<f32>,
     @location(1) image_tex: vec2<f32>,
+    @location(2) normal: vec3<f32>,    // world space
+    @location(3) world_pos: vec3<f32>,
+    @location(4) value: f32,           // channel value before scaling
+    @location(5) corner: vec2<f32>,    // across a point, -0.5 to 0.5
+    @location(6) cell_color: vec4<f32>, // mean colour of a bar's pixels
 };
 
 // @group(1) @binding(1)
 // var<uniform> pos: array<vec2<f32>, 6>;
 
-// relative positions of triangles in a quad
+// relative positions of triangles in a quad, as the strips of mesh::Indices
+// make them, and the corners of a point
 const offsets = array<vec2<i32>, 6>(
     vec2<i32>(0, 0),
     vec2<i32>(1, 1),
@@ -41,67 +80,454 @@ const offsets = array<vec2<i32>, 6>(
     vec2<i32>(1, 1)
 );
 
+// a turn in radians, the hue circle
+const TAU: f32 = 6.283185307179586;
+
 @vertex
 fn vs_main(
-    @builtin(vertex_index) index: u32,
+    @builtin(vertex_index) vertex: u32,
 ) -> VertexOutput {
+    if shading.bars != 0u {
+        return bar_vertex(vertex);
+    }
     var out: VertexOutput;
-    // number of vertexes in a quad, 2 triangles, 6 vertexes
-    let quadsize = 6u;
-    let iquad = index / quadsize;
-    let quadvert = index % quadsize;
-    let pos = vec2<i32>(i32(iquad % mesh_desc.quads_in_row),
-        i32(iquad / mesh_desc.quads_in_row));
-    var offset: vec2<i32>;
-    switch quadvert {
-        case 0u { offset = vec2<i32>(0, 0); }
-        case 1u { offset = vec2<i32>(1, 1); }
-        case 2u { offset = vec2<i32>(0, 1); }
-        case 3u { offset = vec2<i32>(0, 0); }
-        case 4u { offset = vec2<i32>(1, 0); }
-        case 5u { offset = vec2<i32>(1, 1); }
-        default { offset = vec2<i32>(0, 0); }
+    // the index is of a grid vertex, the index buffer makes the triangles.
+    // Points are drawn without it, a quad of two triangles for each grid
+    // vertex unless the device's own points do.
+    var index = vertex;
+    var corner = vec2<f32>(0.0);
+    if shading.points == 6u {
+        index = vertex / 6u;
+        // constant arrays only take constant indexes
+        var corners = offsets;
+        corner = vec2<f32>(corners[vertex % 6u]) - 0.5;
+    }
+    var ring = 0u;
+    if mesh_desc.skirt > 0.0 {
+        ring = 1u;
+    }
+    let rowsize = mesh_desc.quads_in_row + 1u + 2u * ring;
+    let pos = vec2<i32>(vec2<u32>(index % rowsize, index / rowsize))
+        - i32(ring);
+    // a skirt vertex hangs below the edge vertex next to it
+    let edge = clamp(pos, vec2<i32>(0),
+        vec2<i32>(vec2<u32>(mesh_desc.quads_in_row, mesh_desc.rows_of_quads)));
+    var drop = 0.0;
+    if any(edge != pos) {
+        drop = mesh_desc.skirt;
     }
     // let tcoords = pos + offset;
     // let coords = vec2<f32>(f32(tcoords.x), f32(tcoords.y));
-    let coords = vec2<f32>(pos + offset);
+    let coords = vec2<f32>(edge);
 
     out.wire_tex = coords;
 
-    out.image_tex.x = coords.x / f32(mesh_desc.quads_in_row);
-    out.image_tex.y = 1.0 - coords.y / f32(mesh_desc.rows_of_quads);
+    out.image_tex = image_coords(coords);
 
     let x = coords.x * mesh_desc.xscale + mesh_desc.xoffset;
     let y = coords.y * mesh_desc.yscale + mesh_desc.yoffset;
-    let dim = textureDimensions(image_tex);
-    let icoords = vec2<i32>(
-        i32(coords.x * f32(dim.x) / f32(mesh_desc.quads_in_row + 1u) + 0.5),
-        i32((f32(mesh_desc.rows_of_quads) - coords.y) * f32(dim.y)
-            / f32(mesh_desc.rows_of_quads + 1u) + 0.5)
-    );
-
-    let rgba = textureLoad(image_tex, icoords, 0);
-    var z: f32;
-    switch mesh_desc.channel {
-        // case 0 { z = sqrt(dot(rgba.rgb, rgba.rgb)) / 3.0; }
-        case 1 { z = rgba.r * mesh_desc.zscale + mesh_desc.zoffset; }
-        case 2 { z = rgba.g * mesh_desc.zscale + mesh_desc.zoffset; }
-        case 3 { z = rgba.b * mesh_desc.zscale + mesh_desc.zoffset; }
-        default { z = sqrt(dot(rgba.rgb, rgba.rgb)) 
-            * mesh_desc.zscale + mesh_desc.zoffset; }
-    }
+    out.value = value(coords);
+    let z = out.value * mesh_desc.zscale + mesh_desc.zoffset - drop;
     // let z = sqrt(dot(rgba.rgb, rgba.rgb)) / 3.0;
     out.clip_position = camera.view_proj * vec4<f32>(x, y, z, 1.0);
+    if shading.points > 0u {
+        // skirts only hide cracks in a surface, clip them away
+        if drop > 0.0 {
+            out.clip_position = vec4<f32>(0.0, 0.0, -1.0, 1.0);
+        }
+        let size = 2.0 * shading.point_size / camera.viewport.xy;
+        out.clip_position += vec4<f32>(
+            corner * size * out.clip_position.w, 0.0, 0.0);
+    }
+    out.corner = corner;
+
+    let world = camera.model * vec4<f32>(x, y, z, 1.0);
+    out.world_pos = world.xyz / world.w;
+    if shading.lighting != 0u {
+        let n = mat3x3<f32>(camera.normal[0].xyz, camera.normal[1].xyz,
+            camera.normal[2].xyz) * grid_normal(coords);
+        out.normal = normalize(n);
+    } else {
+        out.normal = vec3<f32>(0.0, 0.0, 1.0);
+    }
+
+    return out;
+}
+
+// A box on each quad, shading.bars vertexes to a quad. The top is at the
+// mean value of the pixels between the quad's corners, the sides drop to
+// the height of a zero value.
+fn bar_vertex(vertex: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let quad = vertex / shading.bars;
+    let face = vertex % shading.bars / 6u; // the top, then the sides
+    // constant arrays only take constant indexes
+    var corners = offsets;
+    let uv = vec2<f32>(corners[vertex % 6u]);
+    let first = vec2<f32>(vec2<u32>(quad % mesh_desc.quads_in_row,
+        quad / mesh_desc.quads_in_row));
+
+    // The pixels of the mip level the heights come from, each the mean of
+    // the image pixels it covers and no larger than a quad, so a few of
+    // them make the mean of the quad. The colour texture has the same
+    // levels. Image rows run down, so the corners are sorted. Large quads
+    // without mip levels take every few pixels, at most 16 along a side.
+    let level = i32(mesh_desc.level);
+    let last = vec2<i32>(textureDimensions(height_tex, level)) - 1;
+    let a = texel(first) >> vec2<u32>(mesh_desc.level);
+    let b = texel(first + 1.0) >> vec2<u32>(mesh_desc.level);
+    let lo = clamp(min(a, b), vec2<i32>(0), last);
+    let hi = clamp(max(a, b), vec2<i32>(0), last);
+    let step = max((hi - lo + 16) / 16, vec2<i32>(1));
+    var sum = 0.0;
+    var color = vec4<f32>(0.0);
+    var n = 0.0;
+    for (var y = lo.y; y <= hi.y; y += step.y) {
+        for (var x = lo.x; x <= hi.x; x += step.x) {
+            let p = vec2<i32>(x, y);
+            sum += channel_value(load_height(p, level));
+            color += textureLoad(image_tex, p, level);
+            n += 1.0;
+        }
+    }
+    out.value = transfer(sum / n);
+    out.cell_color = color / n;
 
+    let top = out.value * mesh_desc.zscale + mesh_desc.zoffset;
+    let base = mesh_desc.zoffset;
+    let up = mix(base, top, uv.y);
+    var pos: vec3<f32>; // grid coordinates and z
+    var normal: vec3<f32>;
+    switch face {
+        case 0u {
+            pos = vec3<f32>(first + uv, top);
+            normal = vec3<f32>(0.0, 0.0, 1.0);
+        }
+        case 1u {
+            pos = vec3<f32>(first.x + uv.x, first.y, up);
+            normal = vec3<f32>(0.0, -1.0, 0.0);
+        }
+        case 2u {
+            pos = vec3<f32>(first.x + uv.x, first.y + 1.0, up);
+            normal = vec3<f32>(0.0, 1.0, 0.0);
+        }
+        case 3u {
+            pos = vec3<f32>(first.x, first.y + uv.x, up);
+            normal = vec3<f32>(-1.0, 0.0, 0.0);
+        }
+        default {
+            pos = vec3<f32>(first.x + 1.0, first.y + uv.x, up);
+            normal = vec3<f32>(1.0, 0.0, 0.0);
+        }
+    }
+
+    out.wire_tex = first + uv;
+    out.image_tex = image_coords(pos.xy);
+    let x = pos.x * mesh_desc.xscale + mesh_desc.xoffset;
+    let y = pos.y * mesh_desc.yscale + mesh_desc.yoffset;
+    out.clip_position = camera.view_proj * vec4<f32>(x, y, pos.z, 1.0);
+    let world = camera.model * vec4<f32>(x, y, pos.z, 1.0);
+    out.world_pos = world.xyz / world.w;
+    // lit() turns the normal to the viewer
+    out.normal = normalize(mat3x3<f32>(camera.normal[0].xyz,
+        camera.normal[1].xyz, camera.normal[2].xyz) * normal);
     return out;
 }
 
+// z of the surface at a grid point, see mesh::Descriptor::grid
+fn height(coords: vec2<f32>) -> f32 {
+    return value(coords) * mesh_desc.zscale + mesh_desc.zoffset;
+}
+
+// Height value of the image at a grid point
+fn value(coords: vec2<f32>) -> f32 {
+    return transfer(sample_value(coords));
+}
+
+// Height value of a channel value, see mesh::Descriptor::transfer
+fn transfer(v: f32) -> f32 {
+    if mesh_desc.remap == 0u {
+        return v;
+    }
+    var t = clamp((v - mesh_desc.lo) / (mesh_desc.hi - mesh_desc.lo),
+        0.0, 1.0);
+    switch mesh_desc.curve {
+        case 1u {
+            t = log(1.0 + 1000.0 * t) / log(1001.0);
+        }
+        case 2u {
+            t = pow(t, mesh_desc.power);
+        }
+        case 3u {
+            let x = t * 63.0;
+            let i = min(u32(x), 62u);
+            let j = i + 1u;
+            t = mix(mesh_desc.cdf[i / 4u][i % 4u],
+                mesh_desc.cdf[j / 4u][j % 4u], x - f32(i));
+        }
+        default {}
+    }
+    if mesh_desc.invert != 0u {
+        t = 1.0 - t;
+    }
+    let range = channel_range();
+    return mix(range.x, range.y, t);
+}
+
+// Channel value of the image at a grid point, see height::Field::sample
+// and mesh::Descriptor::grid
+fn sample_value(coords: vec2<f32>) -> f32 {
+    // the pixels of the mip level
+    let p = (pixel(coords) + 0.5) / f32(1u << mesh_desc.level) - 0.5;
+    let base = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    switch mesh_desc.sampling {
+        case 1u {
+            return from_point(mix(
+                mix(load_point(base), load_point(base + vec2<i32>(1, 0)), f.x),
+                mix(load_point(base + vec2<i32>(0, 1)),
+                    load_point(base + vec2<i32>(1, 1)), f.x),
+                f.y));
+        }
+        case 2u {
+            var wx = catmull_rom(f.x);
+            var wy = catmull_rom(f.y);
+            var sum = vec2<f32>(0.0);
+            for (var j = 0; j < 4; j++) {
+                for (var i = 0; i < 4; i++) {
+                    sum += wx[i] * wy[j]
+                        * load_point(base + vec2<i32>(i - 1, j - 1));
+                }
+            }
+            return from_point(sum);
+        }
+        default {
+            return load_value(vec2<i32>(floor(p + 0.5)));
+        }
+    }
+}
+
+// Channel value of a pixel to interpolate. Hue goes round a circle, so it
+// is interpolated as a direction, or the reds either side of 0 would
+// pass through every other hue.
+fn load_point(p: vec2<i32>) -> vec2<f32> {
+    let v = load_value(p);
+    if mesh_desc.channel == 8 {
+        let angle = TAU * v;
+        return vec2<f32>(cos(angle), sin(angle));
+    }
+    return vec2<f32>(v, 0.0);
+}
+
+fn from_point(v: vec2<f32>) -> f32 {
+    if mesh_desc.channel == 8 {
+        return fract(atan2(v.y, v.x) / TAU + 1.0);
+    }
+    return v.x;
+}
+
+// Position in pixels of the image under a grid point, the corner
+// vertexes on the corner pixels. See mesh::Descriptor::pixel.
+fn pixel(coords: vec2<f32>) -> vec2<f32> {
+    let last = vec2<f32>(textureDimensions(height_tex)) - 1.0;
+    return vec2<f32>(
+        coords.x * last.x / f32(mesh_desc.quads_in_row),
+        (f32(mesh_desc.rows_of_quads) - coords.y) * last.y
+            / f32(mesh_desc.rows_of_quads));
+}
+
+// Texture coordinates of the centre of the pixel under a grid point
+fn image_coords(coords: vec2<f32>) -> vec2<f32> {
+    return (pixel(coords) + 0.5) / vec2<f32>(textureDimensions(height_tex));
+}
+
+// Nearest pixel of the image to a grid point
+fn texel(coords: vec2<f32>) -> vec2<i32> {
+    return vec2<i32>(floor(pixel(coords) + 0.5));
+}
+
+// Pixel of a mip level of the height texture, a single channel, as a
+// grey image has, spread over rgb
+fn load_height(p: vec2<i32>, level: i32) -> vec4<f32> {
+    let t = textureLoad(height_tex, p, level);
+    if mesh_desc.grey != 0u {
+        return vec4<f32>(t.rrr, 1.0);
+    }
+    return t;
+}
+
+// Channel value of a pixel of the mip level, clamped to the edge
+fn load_value(p: vec2<i32>) -> f32 {
+    let level = i32(mesh_desc.level);
+    let last = vec2<i32>(textureDimensions(height_tex, level)) - 1;
+    return channel_value(
+        load_height(clamp(p, vec2<i32>(0), last), level));
+}
+
+// Weights of the four pixels around a point t of the way from the second
+// to the third
+fn catmull_rom(t: f32) -> vec4<f32> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    return vec4<f32>(
+        -0.5 * t3 + t2 - 0.5 * t,
+        1.5 * t3 - 2.5 * t2 + 1.0,
+        -1.5 * t3 + 2.0 * t2 + 0.5 * t,
+        0.5 * t3 - 0.5 * t2);
+}
+
+fn channel_value(rgba: vec4<f32>) -> f32 {
+    var v: f32;
+    switch mesh_desc.channel {
+        // case 0 { v = sqrt(dot(rgba.rgb, rgba.rgb)) / 3.0; }
+        case 1 { v = rgba.r; }
+        case 2 { v = rgba.g; }
+        case 3 { v = rgba.b; }
+        case 6 { v = luma(rgba.rgb); }
+        case 7 { v = lightness(luma(rgba.rgb)); }
+        case 8 { v = hsv(rgba.rgb).x; }
+        case 9 { v = hsv(rgba.rgb).y; }
+        case 10 { v = hsv(rgba.rgb).z; }
+        case 11 { v = rgba.a; }
+        case 12 { v = dot(mesh_desc.mix, rgba); }
+        default { v = sqrt(dot(rgba.rgb, rgba.rgb)); }
+    }
+    return v;
+}
+
+// Lowest and highest value of the channel, see cli::Channel::range
+fn channel_range() -> vec2<f32> {
+    var range = vec2<f32>(0.0, 1.0);
+    switch mesh_desc.channel {
+        case 0, 4, 5 { range.y = sqrt(3.0); }
+        case 12 {
+            range.x = dot(min(mesh_desc.mix, vec4<f32>(0.0)), vec4<f32>(1.0));
+            range.y = dot(max(mesh_desc.mix, vec4<f32>(0.0)), vec4<f32>(1.0));
+            if range.y <= range.x {
+                range.y = range.x + 1.0;
+            }
+        }
+        default {}
+    }
+    return range;
+}
+
+// Rec.709 weights of linear rgb, the relative luminance, see height::luma
+fn luma(rgb: vec3<f32>) -> f32 {
+    return dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// CIE L* of a relative luminance, 0 to 1, see height::lightness
+fn lightness(y: f32) -> f32 {
+    if y > 216.0 / 24389.0 {
+        return 1.16 * pow(y, 1.0 / 3.0) - 0.16;
+    }
+    return 24389.0 / 27.0 * y / 100.0;
+}
+
+// Hue, saturation and value, see height::hsv
+fn hsv(rgb: vec3<f32>) -> vec3<f32> {
+    let top = max(max(rgb.r, rgb.g), rgb.b);
+    let delta = top - min(min(rgb.r, rgb.g), rgb.b);
+    var hue = 0.0;
+    if delta <= 0.0 {
+        hue = 0.0;
+    } else if top == rgb.r {
+        hue = (rgb.g - rgb.b) / delta;
+    } else if top == rgb.g {
+        hue = (rgb.b - rgb.r) / delta + 2.0;
+    } else {
+        hue = (rgb.r - rgb.g) / delta + 4.0;
+    }
+    if hue < 0.0 {
+        hue += 6.0;
+    }
+    var saturation = 0.0;
+    if top > 0.0 {
+        saturation = delta / top;
+    }
+    return vec3<f32>(hue / 6.0, saturation, top);
+}
+
+// Fully saturated colour of a hue
+fn hue_color(hue: f32) -> vec3<f32> {
+    let k = vec3<f32>(1.0, 2.0 / 3.0, 1.0 / 3.0);
+    return clamp(abs(fract(hue + k) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0),
+        vec3<f32>(1.0));
+}
+
+// Mesh space normal from central differences of the neighbouring
+// heights, one sided at the edges of the grid.
+fn grid_normal(coords: vec2<f32>) -> vec3<f32> {
+    let last = vec2<f32>(f32(mesh_desc.quads_in_row),
+        f32(mesh_desc.rows_of_quads));
+    let left = max(coords - vec2<f32>(1.0, 0.0), vec2<f32>(0.0));
+    let right = min(coords + vec2<f32>(1.0, 0.0), last);
+    let down = max(coords - vec2<f32>(0.0, 1.0), vec2<f32>(0.0));
+    let up = min(coords + vec2<f32>(0.0, 1.0), last);
+    let dzdx = (height(right) - height(left))
+        / ((right.x - left.x) * mesh_desc.xscale);
+    let dzdy = (height(up) - height(down))
+        / ((up.y - down.y) * mesh_desc.yscale);
+    return normalize(vec3<f32>(-dzdx, -dzdy, 1.0));
+}
+
+// Lights the surface colour. The normal is flipped when the back of the
+// surface is seen.
+fn lit(color: vec4<f32>, in: VertexOutput) -> vec4<f32> {
+    if shading.lighting == 0u {
+        return color;
+    }
+    let view_dir = normalize(camera.view_pos.xyz - in.world_pos);
+    var n = normalize(in.normal);
+    if dot(n, view_dir) < 0.0 {
+        n = -n;
+    }
+    let light_dir = normalize(shading.light_dir.xyz);
+    let diffuse = max(dot(n, light_dir), 0.0) * shading.light.y;
+    var specular = 0.0;
+    if shading.lighting == 2u && diffuse > 0.0 {
+        let half_dir = normalize(light_dir + view_dir);
+        specular = pow(max(dot(n, half_dir), 0.0), shading.light.w)
+            * shading.light.z;
+    }
+    return vec4<f32>(color.rgb * (shading.light.x + diffuse)
+        + vec3<f32>(specular), color.a);
+}
+
+// Draws contour lines over the colour, antialiased over a pixel. The
+// distance to a line is measured in pixels using the screen space rate of
+// change of the value.
+fn contour(color: vec4<f32>, value: f32, dv: f32) -> vec4<f32> {
+    let dv = max(dv, 1e-6);
+    var dist = 1e6;
+    if shading.interval > 0.0 {
+        let d = value / shading.interval;
+        dist = abs(fract(d + 0.5) - 0.5) * shading.interval / dv;
+    }
+    for (var i = 0u; i < shading.nlevels; i++) {
+        let level = shading.levels[i / 4u][i % 4u];
+        dist = min(dist, abs(value - level) / dv);
+    }
+    let cover = clamp(shading.width * 0.5 + 0.5 - dist, 0.0, 1.0);
+    return vec4<f32>(mix(color.rgb, shading.contour_color.rgb, cover),
+        color.a);
+}
+
 // Fragment shader
 
 @group(0) @binding(0)
 var image_tex: texture_2d<f32>;
 @group(0)@binding(1)
 var image_sampler: sampler;
+// Same image at full precision, for the heights
+@group(0) @binding(2)
+var height_tex: texture_2d<f32>;
+// Colormap lookup table
+@group(0) @binding(3)
+var colormap_tex: texture_1d<f32>;
+@group(0) @binding(4)
+var colormap_sampler: sampler;
 
 // Hardware wire frame
 @fragment
@@ -110,6 +536,40 @@ fn fs_wire(in: VertexOutput) -> @location(0) vec4<f32> {
 
 }
 
+// Wire frame for devices without line polygons. The grid coordinates are
+// whole on the edges of the quads and x - y is whole on their diagonals,
+// the triangle edges are drawn a pixel wide and the rest discarded.
+@fragment
+fn fs_wire_grid(in: VertexOutput) -> @location(0) vec4<f32> {
+    // derivatives before the discard
+    let lines = grid_lines(in.wire_tex);
+    let cover = grid_cover(lines, fwidth(lines));
+    if cover <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(cover, cover, cover, 1.0);
+}
+
+// Whole on the edges of the triangles. Only fragment entry points call
+// these, but naga's GL backend writes every function into the vertex
+// shader as well, where fwidth doesn't compile, so the entry points take
+// the derivatives.
+fn grid_lines(wire_tex: vec2<f32>) -> vec3<f32> {
+    return vec3<f32>(wire_tex, wire_tex.x - wire_tex.y);
+}
+
+// How much of the pixel the triangle edges cover
+fn grid_cover(lines: vec3<f32>, dl: vec3<f32>) -> f32 {
+    let d = abs(fract(lines + 0.5) - 0.5) / max(dl, vec3<f32>(1e-6));
+    return clamp(1.0 - min(min(d.x, d.y), d.z), 0.0, 1.0);
+}
+
+// Grid lines over a filled surface
+fn overlay(color: vec4<f32>, cover: f32) -> vec4<f32> {
+    return vec4<f32>(mix(color.rgb, shading.wire_color.rgb,
+        cover * shading.wire_color.a), color.a);
+}
+
 @fragment
 fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
     return textureSample(image_tex, image_sampler, in.image_tex);
@@ -117,25 +577,60 @@ fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
 
 @fragment
 fn fs_fill(in: VertexOutput) -> @location(0) vec4<f32> {
+    // derivatives before any branching
+    let dv = fwidth(in.value);
+    let lines = grid_lines(in.wire_tex);
+    let cover = grid_cover(lines, fwidth(lines));
     var out: vec4<f32>;
-    let rgba = textureSample(image_tex, image_sampler, in.image_tex);
+    var rgba = textureSample(image_tex, image_sampler, in.image_tex);
+    if shading.bars != 0u {
+        rgba = in.cell_color;
+    }
     switch mesh_desc.channel {
         case 0 { out = rgba; }
         case 1 { out = vec4<f32>(rgba.r, 0.0, 0.0, 1.0); }
         case 2 { out = vec4<f32>(0.0, rgba.g, 0.0, 1.0); }
         case 3 { out = vec4<f32>(0.0, 0.0, rgba.b, 1.0); }
+        // the luminance of the pixel
+        case 4, 5, 6, 7 {
+            let grey = luma(rgba.rgb);
+            out = vec4<f32>(grey, grey, grey, 1.0);
+        }
+        case 8 { out = vec4<f32>(hue_color(hsv(rgba.rgb).x), 1.0); }
+        // the value across the range of the channel
         default {
-            let grey = sqrt(dot(rgba.rgb, rgba.rgb)) / 3.0;
-            return vec4<f32>(grey, grey, grey, 1.0);
+            let range = channel_range();
+            let grey = (channel_value(rgba) - range.x) / (range.y - range.x);
+            out = vec4<f32>(grey, grey, grey, 1.0);
         }
     }
-    return out;
+    // points drawn as quads are cut to a disc
+    if dot(in.corner, in.corner) > 0.25 {
+        discard;
+    }
+    return overlay(contour(lit(out, in), in.value, dv), cover);
+}
+
+// Colour by height, across the range of the channel
+@fragment
+fn fs_colormap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dv = fwidth(in.value);
+    let lines = grid_lines(in.wire_tex);
+    let cover = grid_cover(lines, fwidth(lines));
+    let range = channel_range();
+    let t = clamp((in.value - range.x) / (range.y - range.x), 0.0, 1.0);
+    let color = textureSample(colormap_tex, colormap_sampler, t);
+    // points drawn as quads are cut to a disc
+    if dot(in.corner, in.corner) > 0.25 {
+        discard;
+    }
+    return overlay(contour(lit(color, in), in.value, dv), cover);
 }
 
 @fragment
 fn fs_grey(in: VertexOutput) -> @location(0) vec4<f32> {
     let rgba = textureSample(image_tex, image_sampler, in.image_tex);
-    let grey = sqrt(dot(rgba.rgb, rgba.rgb)) / 3.0;
+    let grey = luma(rgba.rgb);
     return vec4<f32>(grey, grey, grey, 1.0);
 }
 
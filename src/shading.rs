@@ -0,0 +1,127 @@
+// Fragment shading parameters shared by every layer.
+use wgpu::util::DeviceExt;
+use crate::{cli, mesh};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Uniform {
+    light_dir: [f32; 4], // towards the light, world space
+    light: [f32; 4],     // ambient, diffuse, specular, shininess
+    lighting: u32,       // cli::Lighting
+    nlevels: u32,        // number of contour levels used
+    interval: f32,       // contour interval, 0 for none
+    width: f32,          // contour width in pixels
+    contour_color: [f32; 4],
+    // Uniform arrays have a 16 byte stride, so four levels to an element
+    levels: [[f32; 4]; cli::MAX_CONTOUR_LEVELS / 4],
+    wire_color: [f32; 4], // overlay grid colour and opacity, 0 for none
+    point_size: f32,      // pixels
+    points: u32,          // vertexes per point, 0 for the surface
+    bars: u32,            // vertexes per bar, 0 for the surface
+    _padding: u32,        // to the 16 byte size of the WGSL struct
+}
+
+impl Uniform {
+    pub fn new(args: &cli::Args) -> Self {
+        let light = &args.light;
+        let (sin_az, cos_az) = light.azimuth.to_radians().sin_cos();
+        let (sin_el, cos_el) = light.elevation.to_radians().sin_cos();
+        let contour = &args.contour;
+        let [r, g, b] = contour.color;
+        let mut levels = [[0.0; 4]; cli::MAX_CONTOUR_LEVELS / 4];
+        for (i, level) in contour.levels.iter().enumerate() {
+            levels[i / 4][i % 4] = *level;
+        }
+        let overlay = &args.overlay;
+        let [wr, wg, wb] = overlay.color;
+        let opacity = if args.display == cli::DisplayMode::Overlay {
+            overlay.opacity
+        } else {
+            0.0
+        };
+        Self {
+            light_dir: [cos_el * cos_az, cos_el * sin_az, sin_el, 0.0],
+            light: [
+                light.ambient,
+                1.0 - light.ambient,
+                light.specular,
+                light.shininess,
+            ],
+            lighting: light.lighting as u32,
+            nlevels: contour.nlevels as u32,
+            interval: contour.interval,
+            width: contour.width,
+            contour_color: [r, g, b, 1.0],
+            levels,
+            wire_color: [wr, wg, wb, opacity],
+            point_size: args.point_size,
+            points: match args.geometry() {
+                mesh::Geometry::Points(per_point) => per_point,
+                _ => 0,
+            },
+            bars: match args.geometry() {
+                mesh::Geometry::Bars => mesh::BAR_VERTICES,
+                _ => 0,
+            },
+            _padding: 0,
+        }
+    }
+}
+
+// Same arrangement as mesh::Data, one buffer written in place.
+pub struct Data {
+    pub uniform: Uniform,
+    buffer: wgpu::Buffer,
+    pub bind: wgpu::BindGroup,
+}
+
+impl Data {
+    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("shading::Data bind_group_layout"),
+        })
+    }
+
+    pub fn new(
+        uniform: Uniform,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("shading::Data buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("shading::Data bind_group"),
+        });
+        Self {
+            uniform,
+            buffer,
+            bind,
+        }
+    }
+
+    pub fn update(&mut self, uniform: Uniform, queue: &wgpu::Queue) {
+        if bytemuck::bytes_of(&uniform) != bytemuck::bytes_of(&self.uniform) {
+            self.uniform = uniform;
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+}
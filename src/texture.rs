@@ -1,39 +1,157 @@
 // Wireframe texture
+use std::borrow::Cow;
 use std::num::NonZeroU32;
 
-use anyhow::*;
 use image::GenericImageView;
+#[allow(unused_imports)]
 use image::*;
 
-// Texture with a bind group
+use crate::cli::Encoding;
+use crate::error::{Error, Result};
+use crate::height;
+
+// Texture with its view and sampler
+#[allow(dead_code)] // resources are held for the life of the bind group
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
-    pub bind_group_layout: wgpu::BindGroupLayout,
-    pub bind_group: wgpu::BindGroup,
+    pub view_dimension: wgpu::TextureViewDimension,
 }
 
 impl Texture {
+    #[allow(dead_code)]
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, label)
+        let img = image::load_from_memory(bytes)
+            .map_err(|err| Error::Decode(label.into(), err))?;
+        Self::from_image(device, queue, &img, false,
+            wgpu::AddressMode::ClampToEdge, label)
     }
 
+    // Colour texture, sampled by the fragment shaders. The mip levels,
+    // box filtered as the heights are, give the bars the mean colour of
+    // their quads. The sampler keeps to level 0.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
+        mipmaps: bool,
+        address_mode: wgpu::AddressMode,
+        label: &str,
+    ) -> Result<Self> {
+        // Most images are stored using sRGB so we need to reflect that here.
+        let mut levels = vec![img.to_rgba8()];
+        let count = if mipmaps {
+            32 - img.width().max(img.height()).leading_zeros()
+        } else { 1 };
+        for _ in 1..count {
+            let next = halve(levels.last().unwrap());
+            levels.push(next);
+        }
+        let levels: Vec<&[u8]> = levels.iter().map(|l| l.as_raw().as_slice())
+            .collect();
+        Self::create(device, queue, img.dimensions(),
+            wgpu::TextureFormat::Rgba8UnormSrgb, &levels,
+            address_mode, label)
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        let size = self.texture.size();
+        (size.width, size.height)
+    }
+
+    // 1D colour lookup table, clamped so the ends don't wrap into each
+    // other.
+    pub fn from_lut(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lut: &[[u8; 4]],
+        label: &str,
+    ) -> Result<Self> {
+        Self::create(device, queue, (lut.len() as u32, 1),
+            wgpu::TextureFormat::Rgba8UnormSrgb, &[bytemuck::cast_slice(lut)],
+            wgpu::AddressMode::ClampToEdge, label)
+    }
+
+    // Height texture, loaded by the vertex shader. Keeps the precision of
+    // the source, 16 bit images use Rgba16Unorm when the device has it
+    // and Rgba32Float otherwise. 8 bit sRGB stays sRGB so the hardware
+    // decodes it, sRGB decoded deeper images need a float texture. Grey
+    // images that don't need the hardware to decode sRGB are stored in a
+    // single channel, R8Unorm, R16Unorm or R32Float, a quarter of the
+    // memory, and the shader spreads it over rgb. With mipmaps each level
+    // is box filtered from the one above, in linear values, for grids
+    // coarser than the image.
+    pub fn from_field(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        field: &height::Field,
+        mipmaps: bool,
         label: &str,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let norm16 = device.features()
+            .contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM);
+        let format = match (field.bits(), field.encoding(), field.grey()) {
+            (8, Encoding::Srgb, _) => wgpu::TextureFormat::Rgba8UnormSrgb,
+            (8, Encoding::Linear, true) => wgpu::TextureFormat::R8Unorm,
+            (8, Encoding::Linear, false) => wgpu::TextureFormat::Rgba8Unorm,
+            (16, Encoding::Linear, true) if norm16 =>
+                wgpu::TextureFormat::R16Unorm,
+            (16, Encoding::Linear, false) if norm16 =>
+                wgpu::TextureFormat::Rgba16Unorm,
+            (_, _, true) => wgpu::TextureFormat::R32Float,
+            _ => wgpu::TextureFormat::Rgba32Float,
+        };
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let to_u16 = |c: f32| (c.clamp(0.0, 1.0) * 65535.0).round() as u16;
+        // A single channel keeps red, which grey images have in all three
+        let channels = if format.describe().components == 1 { 1 } else { 4 };
+        let encode = |field: &height::Field| -> Vec<u8> {
+            let texels = field.texels();
+            let values = texels.iter()
+                .flat_map(|t| t[..channels].iter().copied());
+            match format {
+                wgpu::TextureFormat::Rgba8UnormSrgb =>
+                    texels.iter().flat_map(|t| [
+                        height::linear_to_srgb(t[0]),
+                        height::linear_to_srgb(t[1]),
+                        height::linear_to_srgb(t[2]),
+                        to_u8(t[3]),
+                    ]).collect(),
+                wgpu::TextureFormat::Rgba8Unorm
+                | wgpu::TextureFormat::R8Unorm => values.map(to_u8).collect(),
+                wgpu::TextureFormat::Rgba16Unorm
+                | wgpu::TextureFormat::R16Unorm =>
+                    values.flat_map(|c| to_u16(c).to_le_bytes()).collect(),
+                _ => values.flat_map(f32::to_le_bytes).collect(),
+            }
+        };
+        let count = if mipmaps { field.mip_levels() } else { 1 };
+        let mut levels = vec![encode(field)];
+        let mut level = Cow::Borrowed(field);
+        for _ in 1..count {
+            level = Cow::Owned(level.halve());
+            levels.push(encode(&level));
+        }
+        let levels: Vec<&[u8]> = levels.iter().map(Vec::as_slice).collect();
+        Self::create(device, queue, field.dimensions(), format, &levels,
+            wgpu::AddressMode::Repeat, label)
+    }
 
+    fn create(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dimensions: (u32, u32),
+        format: wgpu::TextureFormat,
+        levels: &[&[u8]],
+        address_mode: wgpu::AddressMode,
+        label: &str,
+    ) -> Result<Self> {
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -41,13 +159,19 @@ impl Texture {
         };
         // Make sure the TextureViewDimension is consistent with the
         // TextureDimension.
-        let (dim, dim_view) = if dimensions.1 == 1 {
+        let limits = device.limits();
+        let (dim, dim_view, max) = if dimensions.1 == 1 {
             (wgpu::TextureDimension::D1,
-            wgpu::TextureViewDimension::D1)
+            wgpu::TextureViewDimension::D1, limits.max_texture_dimension_1d)
         } else {
             (wgpu::TextureDimension::D2,
-            wgpu::TextureViewDimension::D2)
+            wgpu::TextureViewDimension::D2, limits.max_texture_dimension_2d)
         };
+        // wgpu would panic on these
+        if dimensions.0 > max || dimensions.1 > max {
+            return Err(Error::TooLarge {
+                name: label.to_string(), dims: dimensions, max: Some(max) });
+        }
 
         // let texture = device.create_texture(&wgpu::TextureDescriptor {
         //     label,
@@ -63,12 +187,11 @@ impl Texture {
             // All textures are stored as 3D, we represent our 2D texture
             // by setting depth to 1.
             size,
-            mip_level_count: 1, // We'll talk about this a little later
+            mip_level_count: levels.len() as u32,
             sample_count: 1,
             // dimension: wgpu::TextureDimension::D2,
             dimension: dim,
-            // Most images are stored using sRGB so we need to reflect that here.
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
             // COPY_DST means that we want to copy data to this texture
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
@@ -84,33 +207,70 @@ impl Texture {
         });
 
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: NonZeroU32::new(dimensions.1),
-            },
-            size,
-        );
+        // Each mip level is half the one above, down to a single texel
+        for (i, bytes) in levels.iter().enumerate() {
+            let width = (dimensions.0 >> i).max(1);
+            let height = (dimensions.1 >> i).max(1);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: i as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                bytes,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(
+                        format.describe().block_size as u32 * width),
+                    rows_per_image: NonZeroU32::new(height),
+                },
+                wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::FilterMode::Nearest,
+            // Samples level 0, the mip levels are loaded by the vertex
+            // shader. Up to half a level the nearest is 0, and unlike 0
+            // it still minifies with min_filter.
+            lod_max_clamp: 0.5,
             ..Default::default()
         });
 
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            view_dimension: dim_view,
+        })
+    }
+}
+
+// The image textures the shaders read, bound together as group 0.
+#[allow(dead_code)]
+pub struct Images {
+    pub color: Texture,
+    pub height: Texture,
+    pub lut: Texture, // colormap
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Images {
+    pub fn new(
+        device: &wgpu::Device,
+        color: Texture,
+        height: Texture,
+        lut: Texture,
+        label: &str,
+    ) -> Self {
         let mut bgl_label = String::from(label);
         bgl_label.push_str(" bind_group_layout");
 
@@ -122,7 +282,7 @@ impl Texture {
                         visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
-                            view_dimension: dim_view,
+                            view_dimension: color.view_dimension,
                             sample_type: wgpu::TextureSampleType::Float {
                                 filterable: true },
                         },
@@ -135,6 +295,36 @@ impl Texture {
                             wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // Only ever loaded, so any float format will do
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: height.view_dimension,
+                            sample_type: wgpu::TextureSampleType::Float {
+                                filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: lut.view_dimension,
+                            sample_type: wgpu::TextureSampleType::Float {
+                                filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
                 label: Some(&bgl_label),
             });
@@ -148,26 +338,62 @@ impl Texture {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view),
+                    resource: wgpu::BindingResource::TextureView(&color.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(&color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&height.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&lut.sampler),
                 },
             ],
             label: Some(&bg_label),
         });
-        Ok(Self {
-            texture,
-            view,
-            sampler,
+        Self {
+            color,
+            height,
+            lut,
             bind_group_layout,
             bind_group
-        })
+        }
     }
 }
 
+// The next mip level of an sRGB image, each pixel the mean of two by two
+// in linear values, as height::Field::halve.
+fn halve(image: &image::RgbaImage) -> image::RgbaImage {
+    let (width, height) = image.dimensions();
+    image::RgbaImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut sum = [0.0f32; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let p = image.get_pixel((2 * x + dx).min(width - 1),
+                (2 * y + dy).min(height - 1));
+            for k in 0..3 {
+                sum[k] += height::srgb_to_linear(p[k] as f32 / 255.0) / 4.0;
+            }
+            sum[3] += p[3] as f32 / 4.0;
+        }
+        image::Rgba([
+            height::linear_to_srgb(sum[0]),
+            height::linear_to_srgb(sum[1]),
+            height::linear_to_srgb(sum[2]),
+            sum[3].round() as u8,
+        ])
+    })
+}
+
 //  Depth texture.
+#[allow(dead_code)]
 pub struct Depth {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -0,0 +1,480 @@
+// Level of detail for images too large to draw as one texture. A pyramid
+// of the image, halved at each level, is cut into tiles of TILE_QUADS
+// quads that are drawn with a vertex per pixel of their level. Each frame
+// the quadtree of tiles is walked down from the coarsest level, tiles out
+// of view are culled and tiles whose quads would cover too many pixels on
+// screen are split into the four tiles of the next finer level.
+use std::collections::HashMap;
+use cgmath::{Matrix4, Vector4};
+use image::{DynamicImage, GenericImageView, ImageBuffer};
+use crate::error::Result;
+use crate::{cli, filter, height, mesh, texture};
+
+const TILE_QUADS: u32 = 256;
+// Tiles are split while a quad covers more pixels than this
+const PIXELS_PER_QUAD: f32 = 3.0;
+// Tiles uploaded per frame, a coarser tile stands in for the rest
+const UPLOADS_PER_FRAME: usize = 8;
+// Tiles kept on the GPU, the least recently drawn are dropped beyond this
+const CACHED_TILES: usize = 512;
+// Depth of the skirts as a fraction of the z scale
+const SKIRT: f32 = 0.05;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct Key {
+    level: u32,
+    x: u32,
+    y: u32,
+}
+
+// The image at every level, level 0 as decoded. A pixel of level n is on
+// every 2^n pixels of the image, the last one may be past its edge. Every
+// level stays in memory for the life of the tiles, the levels below 0 as
+// rgba in the sample type of the image, together a third as many pixels
+// as level 0. Tiles are cut from them as they come into view.
+struct Pyramid {
+    levels: Vec<DynamicImage>,
+}
+
+impl Pyramid {
+    fn new(image: DynamicImage) -> Self {
+        let mut levels = vec![image];
+        loop {
+            let last = levels.last().unwrap();
+            let (width, height) = last.dimensions();
+            if width <= TILE_QUADS + 1 && height <= TILE_QUADS + 1 {
+                break;
+            }
+            let next = halve(last);
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    fn top(&self) -> u32 { self.levels.len() as u32 - 1 }
+
+    fn dimensions(&self, level: u32) -> (u32, u32) {
+        self.levels[level as usize].dimensions()
+    }
+
+    // Number of tiles across and down a level
+    fn tiles(&self, level: u32) -> (u32, u32) {
+        let (width, height) = self.dimensions(level);
+        ((width - 1).div_ceil(TILE_QUADS), (height - 1).div_ceil(TILE_QUADS))
+    }
+
+    // First pixel and number of quads of a tile. Neighbouring tiles share
+    // their edge pixels.
+    fn extent(&self, key: Key) -> ((u32, u32), (u32, u32)) {
+        let (width, height) = self.dimensions(key.level);
+        let first = (key.x * TILE_QUADS, key.y * TILE_QUADS);
+        let quads = (
+            (width - 1 - first.0).min(TILE_QUADS),
+            (height - 1 - first.1).min(TILE_QUADS),
+        );
+        (first, quads)
+    }
+}
+
+// Next level of the pyramid, keeping the sample type so the tiles are
+// read the same way as the image. Pixel q is the tent filtered pixels
+// either side of 2q, so it stays centred on the same spot.
+fn halve(image: &DynamicImage) -> DynamicImage {
+    let color = image.color();
+    let (width, height) = image.dimensions();
+    let dims = (width, height);
+    let half = ((width - 1).div_ceil(2) + 1, (height - 1).div_ceil(2) + 1);
+    match 8 * color.bytes_per_pixel() / color.channel_count() {
+        8 => DynamicImage::ImageRgba8(ImageBuffer::from_raw(half.0, half.1,
+            tent(&image.to_rgba8(), dims, half)).unwrap()),
+        16 => DynamicImage::ImageRgba16(ImageBuffer::from_raw(half.0, half.1,
+            tent(&image.to_rgba16(), dims, half)).unwrap()),
+        _ => DynamicImage::ImageRgba32F(ImageBuffer::from_raw(half.0, half.1,
+            tent(&image.to_rgba32f(), dims, half)).unwrap()),
+    }
+}
+
+trait Sample: Copy {
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Sample for u8 {
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f32(value: f32) -> Self { value.round().clamp(0.0, 255.0) as u8 }
+}
+
+impl Sample for u16 {
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f32(value: f32) -> Self {
+        value.round().clamp(0.0, 65535.0) as u16
+    }
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> f32 { self }
+    fn from_f32(value: f32) -> Self { value }
+}
+
+// Rgba samples of dims halved to half
+fn tent<T: Sample>(image: &[T], dims: (u32, u32), half: (u32, u32)) -> Vec<T> {
+    let (width, height) = dims;
+    let half_width = half.0;
+    // 1/4, 1/2, 1/4 about 2q, clamped to the edges
+    let taps = |q: u32, n: u32| {
+        let c = 2 * q as i64;
+        [(c - 1, 0.25), (c, 0.5), (c + 1, 0.25)]
+            .map(|(i, w)| (i.clamp(0, n as i64 - 1) as u32, w))
+    };
+    // across, then down
+    let mut rows = vec![[0.0f32; 4]; (half_width * height) as usize];
+    for y in 0..height {
+        for x in 0..half_width {
+            let sum = &mut rows[(y * half_width + x) as usize];
+            for (i, w) in taps(x, width) {
+                let p = 4 * (y * width + i) as usize;
+                for k in 0..4 { sum[k] += image[p + k].to_f32() * w; }
+            }
+        }
+    }
+    let mut samples = Vec::with_capacity((4 * half.0 * half.1) as usize);
+    for y in 0..half.1 {
+        for x in 0..half_width {
+            let mut sum = [0.0f32; 4];
+            for (j, w) in taps(y, height) {
+                let p = rows[(j * half_width + x) as usize];
+                for k in 0..4 { sum[k] += p[k] * w; }
+            }
+            samples.extend(sum.map(T::from_f32));
+        }
+    }
+    samples
+}
+
+// A tile on the GPU, with a uniform for each layer
+struct Tile {
+    images: texture::Images,
+    desc: mesh::Descriptor,
+    layers: Vec<mesh::Data>,
+    drawn: u64, // frame the tile was last drawn
+}
+
+pub struct Tiles {
+    pyramid: Pyramid,
+    encoding: Option<cli::Encoding>,
+    filters: Vec<cli::Filter>,
+    lut: Vec<[u8; 4]>,
+    backend: wgpu::Backend,
+    cache: HashMap<Key, Tile>,
+    // Index buffers by grid size, most tiles share one
+    indices: HashMap<(u32, u32), mesh::Indices>,
+    visible: Vec<Key>,
+    pending: bool, // tiles waiting to be uploaded
+    frame: u64,
+}
+
+impl Tiles {
+    pub fn new(
+        image: DynamicImage,
+        encoding: Option<cli::Encoding>,
+        filters: Vec<cli::Filter>,
+        lut: Vec<[u8; 4]>,
+        backend: wgpu::Backend,
+    ) -> Self {
+        let pyramid = Pyramid::new(image);
+        log::info!("Tiling {:?} over {} levels", pyramid.dimensions(0),
+            pyramid.levels.len());
+        Self {
+            pyramid,
+            encoding,
+            filters,
+            lut,
+            backend,
+            cache: HashMap::new(),
+            indices: HashMap::new(),
+            visible: Vec::new(),
+            pending: true,
+            frame: 0,
+        }
+    }
+
+    // Of the whole image
+    pub fn dimensions(&self) -> (u32, u32) { self.pyramid.dimensions(0) }
+
+    // Whether tiles needed by the last update are still to be uploaded
+    pub fn pending(&self) -> bool { self.pending }
+
+    fn root(&self) -> Key { Key { level: self.pyramid.top(), x: 0, y: 0 } }
+
+    // Textures of the coarsest tile, the whole image, to pass to load_root
+    pub fn images(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<texture::Images> {
+        self.tile_images(device, queue, self.root())
+    }
+
+    fn tile_images(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        key: Key,
+    ) -> Result<texture::Images> {
+        let (first, quads) = self.pyramid.extent(key);
+        let crop = self.pyramid.levels[key.level as usize]
+            .crop_imm(first.0, first.1, quads.0 + 1, quads.1 + 1);
+        // Clamped so tiles don't bleed into each other
+        let color = texture::Texture::from_image(device, queue, &crop,
+            false, wgpu::AddressMode::ClampToEdge, "tile image")?;
+        let field = self.heights(key.level, first, (quads.0 + 1, quads.1 + 1));
+        let height = texture::Texture::from_field(
+            device, queue, &field, false, "tile height")?;
+        let lut = texture::Texture::from_lut(
+            device, queue, &self.lut, "tile colormap")?;
+        Ok(texture::Images::new(device, color, height, lut, "tile images"))
+    }
+
+    // Heights of the whole image at the coarsest level
+    pub fn overview(&self) -> height::Field {
+        let top = self.pyramid.top();
+        self.heights(top, (0, 0), self.pyramid.dimensions(top))
+    }
+
+    // Filtered heights of the part of a level, cut from a larger part so
+    // the filters see the same neighbours as they would over the whole
+    // image and tiles match along their edges.
+    fn heights(
+        &self,
+        level: u32,
+        first: (u32, u32),
+        size: (u32, u32),
+    ) -> height::Field {
+        let scale = (1 << level) as f32;
+        let margin = filter::reach(&self.filters, scale);
+        let x = first.0.saturating_sub(margin);
+        let y = first.1.saturating_sub(margin);
+        let crop = self.pyramid.levels[level as usize].crop_imm(x, y,
+            size.0 + first.0 - x + margin, size.1 + first.1 - y + margin);
+        let field = height::Field::from_image(&crop, self.encoding);
+        filter::apply(field, &self.filters, scale)
+            .crop(first.0 - x, first.1 - y, size.0, size.1)
+    }
+
+    // Tile of mesh, the grid of the whole image
+    fn descriptor(&self, mesh: &mesh::Descriptor, key: Key) -> mesh::Descriptor {
+        let (first, quads) = self.pyramid.extent(key);
+        let skirt = (mesh.zscale().abs() * SKIRT).max(f32::EPSILON);
+        mesh.tile(self.dimensions(), 1 << key.level, first, quads, skirt)
+    }
+
+    fn children(&self, key: Key) -> impl Iterator<Item = Key> {
+        let level = key.level - 1;
+        let (nx, ny) = self.pyramid.tiles(level);
+        [(0, 0), (1, 0), (0, 1), (1, 1)].into_iter()
+            .map(move |(i, j)| Key { level, x: 2 * key.x + i, y: 2 * key.y + j })
+            .filter(move |child| child.x < nx && child.y < ny)
+    }
+
+    // Picks the tiles to draw from the camera and uploads the ones
+    // missing, a few at a time.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        mesh: &mesh::Descriptor,
+        args: &cli::Args,
+        view_proj: Matrix4<f32>,
+        viewport: (u32, u32),
+    ) {
+        self.frame += 1;
+        let z_range = mesh::layers(mesh, args).iter()
+            .map(|layer| layer.z_range())
+            .fold((f32::MAX, f32::MIN), |(lo, hi), (a, b)| (lo.min(a), hi.max(b)));
+        let root = self.root();
+        let mut wanted = Vec::new();
+        self.select(root, mesh, z_range, view_proj, viewport,
+            &mut wanted);
+        // coarse tiles first, they stand in for the finer ones
+        wanted.sort_by_key(|key| std::cmp::Reverse(key.level));
+
+        let mut uploads = 0;
+        self.pending = false;
+        self.visible.clear();
+        for key in wanted {
+            let mut key = key;
+            if !self.cache.contains_key(&key) {
+                if uploads < UPLOADS_PER_FRAME {
+                    uploads += 1;
+                    // tried again next frame, not pending so a capture
+                    // doesn't wait on a tile that can't load
+                    if let Err(err) =
+                        self.load(device, queue, layout, mesh, args, key) {
+                        log::warn!("Cannot load tile {:?}: {}", key, err);
+                    }
+                } else {
+                    self.pending = true;
+                }
+                // up to a loaded tile, the root is loaded in build
+                while !self.cache.contains_key(&key) && key != root {
+                    key = Key { level: key.level + 1, x: key.x / 2,
+                        y: key.y / 2 };
+                }
+            }
+            if !self.visible.contains(&key) {
+                self.visible.push(key);
+            }
+        }
+        for key in &self.visible {
+            self.cache.get_mut(key).unwrap().drawn = self.frame;
+        }
+        self.evict();
+    }
+
+    fn select(
+        &self,
+        key: Key,
+        mesh: &mesh::Descriptor,
+        z_range: (f32, f32),
+        view_proj: Matrix4<f32>,
+        viewport: (u32, u32),
+        wanted: &mut Vec<Key>,
+    ) {
+        let desc = self.descriptor(mesh, key);
+        let ((x0, y0), (x1, y1)) = desc.bounds();
+        let (z0, z1) = (z_range.0 - desc.skirt(), z_range.1);
+        let corners = [
+            (x0, y0, z0), (x1, y0, z0), (x0, y1, z0), (x1, y1, z0),
+            (x0, y0, z1), (x1, y0, z1), (x0, y1, z1), (x1, y1, z1),
+        ].map(|(x, y, z)| view_proj * Vector4::new(x, y, z, 1.0));
+        // out of view when every corner is outside the same clip plane
+        let outside = |plane: fn(&Vector4<f32>) -> bool| corners.iter().all(plane);
+        if outside(|c| c.x < -c.w) || outside(|c| c.x > c.w)
+            || outside(|c| c.y < -c.w) || outside(|c| c.y > c.w)
+            || outside(|c| c.z < 0.0) || outside(|c| c.z > c.w) {
+            return;
+        }
+        let (quads_x, quads_y) = desc.quads();
+        let split = key.level > 0 && (corners.iter().any(|c| c.w <= 0.0) || {
+            let (mut lo, mut hi) = ([f32::MAX; 2], [f32::MIN; 2]);
+            for c in &corners {
+                let ndc = [c.x / c.w, c.y / c.w];
+                for k in 0..2 {
+                    lo[k] = lo[k].min(ndc[k]);
+                    hi[k] = hi[k].max(ndc[k]);
+                }
+            }
+            let across = (hi[0] - lo[0]) * 0.5 * viewport.0 as f32;
+            let down = (hi[1] - lo[1]) * 0.5 * viewport.1 as f32;
+            (across / quads_x as f32).max(down / quads_y as f32)
+                > PIXELS_PER_QUAD
+        });
+        if split {
+            for child in self.children(key) {
+                self.select(child, mesh, z_range, view_proj, viewport, wanted);
+            }
+        } else {
+            wanted.push(key);
+        }
+    }
+
+    // Keeps the coarsest tile, made by images, which stands in for the
+    // tiles still to be uploaded and is never evicted
+    pub fn load_root(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        mesh: &mesh::Descriptor,
+        args: &cli::Args,
+        images: texture::Images,
+    ) {
+        self.insert(device, layout, mesh, args, self.root(), images);
+    }
+
+    fn load(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        mesh: &mesh::Descriptor,
+        args: &cli::Args,
+        key: Key,
+    ) -> Result<()> {
+        let images = self.tile_images(device, queue, key)?;
+        self.insert(device, layout, mesh, args, key, images);
+        Ok(())
+    }
+
+    fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        mesh: &mesh::Descriptor,
+        args: &cli::Args,
+        key: Key,
+        images: texture::Images,
+    ) {
+        // Level 0 keeps the colour type of the image, a grey one has a
+        // single channel height texture where the coarser levels are rgba
+        let desc = self.descriptor(mesh, key)
+            .with_format(images.height.texture.format());
+        let layers = mesh::layers(&desc, args).into_iter()
+            .map(|layer| mesh::Data::new(layer, device, layout))
+            .collect();
+        let backend = self.backend;
+        self.indices.entry(desc.grid_dims())
+            .or_insert_with(|| mesh::Indices::new(&desc, device, backend));
+        self.cache.insert(key, Tile { images, desc, layers, drawn: 0 });
+    }
+
+    // Drops the tiles drawn longest ago, never the root or a visible tile.
+    // With more visible than CACHED_TILES the cache is left over its limit.
+    fn evict(&mut self) {
+        if self.cache.len() <= CACHED_TILES {
+            return;
+        }
+        let root = self.root();
+        let mut keys: Vec<(u64, Key)> = self.cache.iter()
+            .filter(|(key, _)| **key != root && !self.visible.contains(key))
+            .map(|(key, tile)| (tile.drawn, *key))
+            .collect();
+        keys.sort_by_key(|(drawn, _)| *drawn);
+        let extra = self.cache.len() - CACHED_TILES;
+        for (_, key) in keys.into_iter().take(extra) {
+            self.cache.remove(&key);
+        }
+    }
+
+    // Same as State::set_args for the layers of every tile
+    pub fn set_args(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        args: &cli::Args,
+    ) {
+        for tile in self.cache.values_mut() {
+            mesh::update_layers(&mut tile.layers,
+                mesh::layers(&tile.desc, args), device, queue, layout);
+        }
+    }
+
+    // Draws a layer of every visible tile, the pipeline and the other
+    // bind groups are already set.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        layer: usize,
+        geometry: mesh::Geometry,
+    ) {
+        for key in &self.visible {
+            let tile = &self.cache[key];
+            render_pass.set_bind_group(0, &tile.images.bind_group, &[]);
+            render_pass.set_bind_group(1, &tile.layers[layer].bind, &[]);
+            self.indices[&tile.desc.grid_dims()]
+                .draw(render_pass, geometry);
+        }
+    }
+}